@@ -0,0 +1,313 @@
+//! Shell completion scripts for `--generate-completions`, built from the clap
+//! definition of the command line so new options are picked up automatically.
+
+use clap::{Arg, ArgAction, Command, ValueEnum};
+
+/// A shell to generate a completion script for.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Returns the completion script for the command in the given shell.
+pub fn generate(shell: Shell, command: &Command) -> String {
+    match shell {
+        Shell::Bash => bash(command),
+        Shell::Zsh => zsh(command),
+        Shell::Fish => fish(command),
+    }
+}
+
+/// Options and flags of the command, leaving out positional arguments.
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|a| !a.is_positional() && !a.is_hide_set())
+}
+
+/// Subcommands of the command, leaving out clap's own `help`.
+fn subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set() && c.get_name() != "help")
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Set | ArgAction::Append)
+}
+
+/// The values an option accepts, if it only accepts a fixed set.
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect()
+}
+
+/// The first line of an argument's help, for the shells that show descriptions.
+fn summary(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|h| h.to_string())
+        .and_then(|h| h.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// The words that complete to the given options, e.g. `--class -h --help`.
+fn option_words(command: &Command) -> Vec<String> {
+    let mut words = Vec::new();
+    for arg in options(command) {
+        if let Some(short) = arg.get_short() {
+            words.push(format!("-{}", short));
+        }
+        if let Some(long) = arg.get_long() {
+            words.push(format!("--{}", long));
+        }
+        for alias in arg.get_visible_aliases().unwrap_or_default() {
+            words.push(format!("--{}", alias));
+        }
+    }
+    words
+}
+
+fn bash(command: &Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+
+    let mut script = format!(
+        "{function}() {{\n    local cur prev words cword\n    _init_completion || return\n\n"
+    );
+    // Options taking a fixed set of values complete those values.
+    script.push_str("    case \"$prev\" in\n");
+    for arg in options(command).filter(|a| takes_value(a)) {
+        let mut names: Vec<String> = arg
+            .get_long()
+            .map(|l| format!("--{}", l))
+            .into_iter()
+            .collect();
+        names.extend(
+            arg.get_visible_aliases()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|a| format!("--{}", a)),
+        );
+        if names.is_empty() {
+            continue;
+        }
+        let values = possible_values(arg);
+        let body = if values.is_empty() {
+            "return".to_string()
+        } else {
+            format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return",
+                values.join(" ")
+            )
+        };
+        script.push_str(&format!(
+            "        {})\n            {}\n            ;;\n",
+            names.join("|"),
+            body
+        ));
+    }
+    script.push_str("    esac\n\n");
+
+    // Complete the options of the subcommand if one was given.
+    script.push_str(
+        "    local i\n    for ((i = 1; i < cword; i++)); do\n        case \"${words[i]}\" in\n",
+    );
+    for sub in subcommands(command) {
+        script.push_str(&format!(
+            "            {})\n                COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return\n                ;;\n",
+            sub.get_name(),
+            option_words(sub).join(" ")
+        ));
+    }
+    script.push_str("        esac\n    done\n\n");
+
+    let mut words = option_words(command);
+    words.extend(subcommands(command).map(|c| c.get_name().to_string()));
+    script.push_str(&format!(
+        "    COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n}}\ncomplete -F {function} {name}\n",
+        words.join(" ")
+    ));
+    script
+}
+
+/// Escapes text for a single-quoted zsh `_arguments` spec.
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+/// `_arguments` specs for the options of the command.
+fn zsh_specs(command: &Command) -> Vec<String> {
+    let mut specs = Vec::new();
+    for arg in options(command) {
+        let help = zsh_escape(&summary(arg.get_help()));
+        let value = if takes_value(arg) {
+            let values = possible_values(arg);
+            let name = arg
+                .get_value_names()
+                .and_then(|names| names.first().map(|n| n.to_string()))
+                .unwrap_or_else(|| arg.get_id().to_string());
+            if values.is_empty() {
+                format!(":{}: ", name)
+            } else {
+                format!(":{}:({})", name, values.join(" "))
+            }
+        } else {
+            String::new()
+        };
+        let mut names: Vec<String> = arg
+            .get_short()
+            .map(|s| format!("-{}", s))
+            .into_iter()
+            .collect();
+        names.extend(arg.get_long().map(|l| format!("--{}", l)));
+        names.extend(
+            arg.get_visible_aliases()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|a| format!("--{}", a)),
+        );
+        for name in names {
+            specs.push(format!("'{}[{}]{}'", name, help, value));
+        }
+    }
+    specs
+}
+
+fn zsh(command: &Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+
+    let mut script =
+        format!("#compdef {name}\n\n{function}() {{\n    local line state\n    _arguments -C \\\n");
+    for spec in zsh_specs(command) {
+        script.push_str(&format!("        {} \\\n", spec));
+    }
+    script.push_str("        '1: :->command' \\\n        '*:: :->args'\n\n");
+
+    script.push_str("    case $state in\n        command)\n            local -a commands\n            commands=(\n");
+    for sub in subcommands(command) {
+        script.push_str(&format!(
+            "                '{}:{}'\n",
+            sub.get_name(),
+            zsh_escape(&summary(sub.get_about()))
+        ));
+    }
+    script.push_str("            )\n            _describe 'command' commands\n            ;;\n        args)\n            case $line[1] in\n");
+    for sub in subcommands(command) {
+        script.push_str(&format!(
+            "                {})\n                    _arguments",
+            sub.get_name()
+        ));
+        for spec in zsh_specs(sub) {
+            script.push_str(&format!(" \\\n                        {}", spec));
+        }
+        script.push_str("\n                    ;;\n");
+    }
+    script.push_str(&format!(
+        "            esac\n            ;;\n    esac\n}}\n\n{function} \"$@\"\n"
+    ));
+    script
+}
+
+/// Escapes text for a single-quoted fish argument.
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// `complete` lines for the options of the command, limited by `condition`.
+fn fish_options(name: &str, command: &Command, condition: &str) -> String {
+    let mut lines = String::new();
+    for arg in options(command) {
+        let mut line = format!("complete -c {} -n '{}'", name, condition);
+        if let Some(short) = arg.get_short() {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if let Some(long) = arg.get_long() {
+            line.push_str(&format!(" -l {}", long));
+        }
+        for alias in arg.get_visible_aliases().unwrap_or_default() {
+            line.push_str(&format!(" -l {}", alias));
+        }
+        if takes_value(arg) {
+            let values = possible_values(arg);
+            if values.is_empty() {
+                line.push_str(" -r");
+            } else {
+                line.push_str(&format!(" -xa '{}'", values.join(" ")));
+            }
+        }
+        line.push_str(&format!(
+            " -d '{}'\n",
+            fish_escape(&summary(arg.get_help()))
+        ));
+        lines.push_str(&line);
+    }
+    lines
+}
+
+fn fish(command: &Command) -> String {
+    let name = command.get_name();
+    let mut script = fish_options(name, command, "__fish_use_subcommand");
+    for sub in subcommands(command) {
+        script.push_str(&format!(
+            "complete -c {} -n '__fish_use_subcommand' -f -a {} -d '{}'\n",
+            name,
+            sub.get_name(),
+            fish_escape(&summary(sub.get_about()))
+        ));
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        script.push_str(&fish_options(name, sub, &condition));
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::CommandFactory;
+
+    /// Whether `script` contains `word` as a whole word, not just as the start of a longer one.
+    fn has_word(script: &str, word: &str) -> bool {
+        script.match_indices(word).any(|(i, _)| {
+            !script[i + word.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '-')
+        })
+    }
+
+    /// How `shell` spells a long option in its script.
+    fn long(shell: Shell, option: &str) -> String {
+        match shell {
+            Shell::Fish => format!("-l {}", option),
+            Shell::Bash | Shell::Zsh => format!("--{}", option),
+        }
+    }
+
+    #[test]
+    fn every_long_option_and_subcommand_is_completed() {
+        let command = Args::command();
+        for &shell in Shell::value_variants() {
+            let script = generate(shell, &command);
+            for sub in std::iter::once(&command).chain(subcommands(&command)) {
+                if sub.get_name() != command.get_name() {
+                    assert!(
+                        has_word(&script, sub.get_name()),
+                        "{:?}: {}",
+                        shell,
+                        sub.get_name()
+                    );
+                }
+                for option in options(sub).filter_map(Arg::get_long) {
+                    let option = long(shell, option);
+                    assert!(has_word(&script, &option), "{:?}: {}", shell, option);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Defaults from `$XDG_CONFIG_HOME/hyprland-minimizer/config.toml`.
+//! Keys are the long option names, e.g. `poll-interval = 500` or
+//! `restore-to = ["original", "current"]`. Flags take `true` or `false`, and
+//! counted flags like `verbose` a number too. Options given on the command line
+//! win over the file, including ones that conflict with it. Unknown keys and
+//! values an option does not take are reported with the file and key.
+
+use crate::Args;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::error::{ContextKind, ContextValue};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use toml_edit::{Document, Value};
+
+/// Returns the path of the config file, which does not have to exist.
+fn path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("hyprland-minimizer").join("config.toml"))
+}
+
+/// Turns a config value into the text it would have on the command line.
+fn option_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        Value::Array(items) => {
+            let items: Option<Vec<String>> = items.iter().map(option_value).collect();
+            Some(items?.join(","))
+        }
+        _ => None,
+    }
+}
+
+/// Checks `text` against the values `arg` takes, so that a bad value is reported
+/// with its key rather than as a command line error.
+fn check_value(path: &Path, key: &str, arg: &Arg, text: &str) -> Result<()> {
+    let mut value = Arg::new("value")
+        .value_parser(arg.get_value_parser().clone())
+        .allow_hyphen_values(true);
+    if let Some(delimiter) = arg.get_value_delimiter() {
+        value = value.value_delimiter(delimiter).num_args(1..);
+    }
+    let check = Command::new("config").no_binary_name(true).arg(value);
+    let error = match check.try_get_matches_from([text]) {
+        Ok(_) => return Ok(()),
+        Err(error) => error,
+    };
+    let reason = match error.get(ContextKind::ValidValue) {
+        Some(ContextValue::Strings(valid)) => format!("expected one of {}", valid.join(", ")),
+        _ => match std::error::Error::source(&error) {
+            Some(source) => source.to_string(),
+            None => error.kind().to_string(),
+        },
+    };
+    bail!(
+        "{}: invalid value '{}' for '{}': {}",
+        path.display(),
+        text,
+        key,
+        reason
+    )
+}
+
+/// Turns a config entry for `arg` into the command line arguments it stands for.
+fn entry_args(path: &Path, key: &str, arg: &Arg, value: &Value) -> Result<Vec<String>> {
+    let flag = format!("--{}", key);
+    let args = match (arg.get_action(), value) {
+        (ArgAction::Count, Value::Integer(count)) => {
+            let count = usize::try_from(*count.value())
+                .map_err(|_| anyhow!("{}: '{}' must not be negative", path.display(), key))?;
+            vec![flag; count]
+        }
+        (ArgAction::SetTrue | ArgAction::Count, Value::Boolean(set)) => match *set.value() {
+            true => vec![flag],
+            false => Vec::new(),
+        },
+        (ArgAction::SetFalse, Value::Boolean(set)) => match *set.value() {
+            true => Vec::new(),
+            false => vec![flag],
+        },
+        (ArgAction::Count, _) => {
+            bail!(
+                "{}: '{}' must be a number, true or false",
+                path.display(),
+                key
+            )
+        }
+        (ArgAction::SetTrue | ArgAction::SetFalse, _) => {
+            bail!("{}: '{}' must be true or false", path.display(), key)
+        }
+        (_, value) => {
+            let text = match value {
+                Value::Boolean(set) => set.value().to_string(),
+                value => option_value(value).ok_or_else(|| {
+                    anyhow!("{}: unsupported value for '{}'", path.display(), key)
+                })?,
+            };
+            check_value(path, key, arg, &text)?;
+            vec![format!("{}={}", flag, text)]
+        }
+    };
+    Ok(args)
+}
+
+/// Whether `arg` conflicts with an option given on the command line, in either direction.
+fn conflicts_with_cli(command: &Command, matches: &ArgMatches, arg: &Arg) -> bool {
+    let given = |other: &Arg| {
+        matches.value_source(other.get_id().as_str()) == Some(ValueSource::CommandLine)
+    };
+    command.get_arg_conflicts_with(arg).into_iter().any(given)
+        || command
+            .get_arguments()
+            .filter(|other| given(other))
+            .any(|other| {
+                command
+                    .get_arg_conflicts_with(other)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == arg.get_id())
+            })
+}
+
+/// Puts the options of the config file in front of the command line `cli`,
+/// skipping any that the command line gives or conflicts with.
+fn merge(
+    path: &Path,
+    cli: &[OsString],
+    matches: &ArgMatches,
+    document: &Document,
+) -> Result<Vec<OsString>> {
+    let command = Args::command();
+    let mut merged = vec![cli[0].clone()];
+    for (key, item) in document.iter() {
+        let arg = command
+            .get_arguments()
+            .find(|a| {
+                a.get_long() == Some(key)
+                    || a.get_all_aliases()
+                        .is_some_and(|aliases| aliases.contains(&key))
+            })
+            .ok_or_else(|| anyhow!("{}: unknown option '{}'", path.display(), key))?;
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            || conflicts_with_cli(&command, matches, arg)
+        {
+            continue;
+        }
+        let value = item
+            .as_value()
+            .ok_or_else(|| anyhow!("{}: '{}' must be a plain value", path.display(), key))?;
+        merged.extend(
+            entry_args(path, key, arg, value)?
+                .into_iter()
+                .map(OsString::from),
+        );
+    }
+    merged.extend(cli.iter().skip(1).cloned());
+    Ok(merged)
+}
+
+/// Parses the command line with options missing there filled in from the config file.
+pub fn parse_args() -> Result<Args> {
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let matches = Args::command().get_matches_from(&cli);
+
+    let Some(path) = path() else {
+        return Ok(Args::from_arg_matches(&matches)?);
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Args::from_arg_matches(&matches)?)
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let document: Document = text
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let merged = merge(&path, &cli, &matches, &document)?;
+    let matches = Args::command()
+        .try_get_matches_from(merged)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e.render()))?;
+    Ok(Args::from_arg_matches(&matches)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Parses `cli` with `config` as the config file.
+    fn parse(config: &str, cli: &[&str]) -> Result<Args> {
+        let cli: Vec<OsString> = std::iter::once("hyprland-minimizer")
+            .chain(cli.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let matches = Args::command().try_get_matches_from(&cli)?;
+        let document: Document = config.parse()?;
+        let merged = merge(Path::new("config.toml"), &cli, &matches, &document)?;
+        Ok(Args::try_parse_from(merged)?)
+    }
+
+    #[test]
+    fn command_line_wins_over_conflicting_options() {
+        let args = parse("quiet = true", &["-v"]).unwrap();
+        assert!(!args.quiet);
+        assert_eq!(args.verbose, 1);
+
+        let args = parse("verbose = 2", &["--quiet"]).unwrap();
+        assert!(args.quiet);
+        assert_eq!(args.verbose, 0);
+    }
+
+    #[test]
+    fn counted_flags_take_a_number_or_a_bool() {
+        assert_eq!(parse("verbose = 2", &[]).unwrap().verbose, 2);
+        assert_eq!(parse("verbose = true", &[]).unwrap().verbose, 1);
+        assert_eq!(parse("verbose = false", &[]).unwrap().verbose, 0);
+        let error = parse("verbose = -1", &[]).unwrap_err().to_string();
+        assert!(error.contains("'verbose'"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_keys_name_the_file_and_key() {
+        let error = parse("pol-interval = 500", &[]).unwrap_err().to_string();
+        assert_eq!(error, "config.toml: unknown option 'pol-interval'");
+    }
+
+    #[test]
+    fn bad_values_name_the_file_and_key() {
+        assert_eq!(
+            parse("poll-interval = 500", &[]).unwrap().poll_interval,
+            500
+        );
+        let error = parse("poll-interval = \"fast\"", &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("config.toml: invalid value 'fast' for 'poll-interval': "),
+            "{}",
+            error
+        );
+        let error = parse("poll-interval = 5", &[]).unwrap_err().to_string();
+        assert!(
+            error.contains("for 'poll-interval': 5 is not in 100.."),
+            "{}",
+            error
+        );
+
+        let error = parse("restore-to = [\"original\", \"nowhere\"]", &[])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("config.toml: invalid value 'original,nowhere' for 'restore-to': "),
+            "{}",
+            error
+        );
+        assert!(error.contains("expected one of"), "{}", error);
+    }
+
+    #[test]
+    fn flags_take_true_or_false_only() {
+        assert!(parse("toggle = true", &[]).unwrap().toggle);
+        assert!(!parse("toggle = false", &[]).unwrap().toggle);
+        let error = parse("toggle = \"yes\"", &[]).unwrap_err().to_string();
+        assert!(
+            error.contains("'toggle' must be true or false"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! A small line-based control socket.
+//! Each client connection sends one command line and receives the reply before
+//! the connection is closed, so it can be driven with e.g. `socat` or `nc -U`.
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+/// The first file descriptor passed by systemd socket activation (`SD_LISTEN_FDS_START`).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the number of sockets systemd passed to us, following `sd_listen_fds(3)`:
+/// `LISTEN_FDS` only applies if `LISTEN_PID` names this process.
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, our_pid: u32) -> u32 {
+    let pid_matches = listen_pid
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| pid == our_pid);
+    if !pid_matches {
+        return 0;
+    }
+    listen_fds
+        .and_then(|fds| fds.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// A bound control socket. A socket file we created ourselves is removed again
+/// when this is dropped, unless another process has replaced it since; one
+/// handed over by systemd stays owned by systemd.
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: Option<PathBuf>,
+    /// The device and inode of the socket file we bound.
+    bound: Option<(u64, u64)>,
+}
+
+/// Returns the device and inode of the file at `path`, without following symlinks.
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+impl ControlSocket {
+    /// Binds the control socket, replacing a stale socket file left behind by a previous run.
+    /// Fails if another process still answers on it.
+    pub async fn bind(path: &Path) -> Result<ControlSocket> {
+        if send(path, "metrics").await?.is_some() {
+            bail!(
+                "Another instance is already listening on {}",
+                path.display()
+            );
+        }
+        if path.exists() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        Ok(ControlSocket {
+            listener,
+            path: Some(path.to_path_buf()),
+            bound: file_id(path),
+        })
+    }
+
+    /// Adopts the listening socket passed by systemd socket activation, if any.
+    pub fn from_systemd() -> Result<Option<ControlSocket>> {
+        let count = listen_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::process::id(),
+        );
+        // Keep the variables from leaking into processes we spawn.
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+        if count == 0 {
+            return Ok(None);
+        }
+
+        // SAFETY: systemd guarantees that the descriptor is open and ours from here on.
+        let std_listener =
+            unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        std_listener
+            .set_nonblocking(true)
+            .context("Failed to configure the socket passed by systemd")?;
+        let listener = UnixListener::from_std(std_listener)
+            .context("Failed to adopt the socket passed by systemd")?;
+        Ok(Some(ControlSocket {
+            listener,
+            path: None,
+            bound: None,
+        }))
+    }
+
+    /// Accepts connections forever, answering each command with the handler's reply.
+    pub async fn serve<F>(self, handler: F)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        loop {
+            let stream = match self.listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("[Control] Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut line = String::new();
+                if let Err(e) = BufReader::new(reader).read_line(&mut line).await {
+                    error!("[Control] Failed to read command: {}", e);
+                    return;
+                }
+                let reply = handler(line.trim());
+                if let Err(e) = writer.write_all(reply.as_bytes()).await {
+                    error!("[Control] Failed to send reply: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Sends one command to the control socket at `path` and returns the reply.
+/// Returns `None` when nothing is listening there.
+pub async fn send(path: &Path, command: &str) -> Result<Option<String>> {
+    let mut stream = match UnixStream::connect(path).await {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to connect to {}", path.display()))
+        }
+    };
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .await
+        .with_context(|| format!("Failed to send command to {}", path.display()))?;
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .await
+        .with_context(|| format!("Failed to read reply from {}", path.display()))?;
+    Ok(Some(reply))
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if self.bound.is_some() && file_id(path) == self.bound {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_fds_counts_sockets_passed_to_this_process() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some(" 42\n"), Some("2"), 42), 2);
+    }
+
+    #[test]
+    fn listen_fds_ignores_sockets_meant_for_another_process() {
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+    }
+
+    #[test]
+    fn listen_fds_ignores_malformed_variables() {
+        assert_eq!(listen_fds(Some("pid"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("many"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "hyprland-minimizer-control-{}-{}.sock",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn bind_replaces_a_stale_socket_but_not_a_live_one() {
+        let path = socket_path("live");
+        // A socket file nobody listens on any more.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let socket = ControlSocket::bind(&path).await.unwrap();
+        tokio::spawn(socket.serve(|_| "ok\n".to_string()));
+
+        let error = ControlSocket::bind(&path).await.err().unwrap();
+        assert!(error.to_string().contains("already listening"));
+        assert_eq!(
+            send(&path, "metrics").await.unwrap().as_deref(),
+            Some("ok\n")
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn drop_keeps_a_socket_file_another_process_bound() {
+        let path = socket_path("replaced");
+        let _ = std::fs::remove_file(&path);
+        let socket = ControlSocket::bind(&path).await.unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        let _other = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(socket);
+
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+//! Daemon mode: a single process serving the tray icons of many minimized
+//! windows over one D-Bus connection. Other invocations hand their window over
+//! through the daemon socket instead of staying around themselves.
+
+use crate::control::{self, ControlSocket};
+use crate::ipc::Compositor;
+use crate::{
+    dbus, get_window_by_address, metrics, restore_all, run_item, Args, RestoreOnExit, WindowInfo,
+};
+use anyhow::{bail, Context, Result};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+
+/// How long to wait for the icons to restore their windows on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the daemon socket path, `$XDG_RUNTIME_DIR/hyprland-minimizer.sock` by default.
+fn socket_path(args: &Args) -> Option<PathBuf> {
+    args.daemon_socket.clone().or_else(|| {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join("hyprland-minimizer.sock"))
+    })
+}
+
+/// Hands the window over to a running daemon. Returns false if no daemon is running.
+pub async fn forward(args: &Args, address: &str) -> Result<bool> {
+    let Some(path) = socket_path(args) else {
+        return Ok(false);
+    };
+    let Some(reply) = control::send(&path, &format!("minimize {}", address)).await? else {
+        return Ok(false);
+    };
+    let reply = reply.trim_end();
+    if let Some(e) = reply.strip_prefix("error: ") {
+        bail!("The daemon could not minimize the window: {}", e);
+    }
+    println!("[Daemon] {}", reply);
+    Ok(true)
+}
+
+/// The state shared by all connections to the daemon socket.
+struct Daemon {
+    args: Arc<Args>,
+    compositor: Arc<dyn Compositor>,
+    conn: Arc<dbus::Connection>,
+    /// The windows an icon is currently served for, keyed by address.
+    managed: Mutex<HashMap<String, WindowInfo>>,
+    /// The object paths of the icons served for the managed windows, keyed by address.
+    items: Mutex<HashMap<String, dbus::ItemPaths>>,
+    /// Where the summary item with the badge is served, see [`dbus::SummaryItem`].
+    summary: dbus::ItemPaths,
+    next_item: AtomicU32,
+    /// Notified once all windows were restored on request, to shut the daemon down.
+    quit: Notify,
+}
+
+impl dbus::Summary for Daemon {
+    fn windows(&self) -> Vec<WindowInfo> {
+        self.managed.lock().unwrap().values().cloned().collect()
+    }
+
+    fn restore(&self, address: &str) {
+        let Some(window_info) = self.managed.lock().unwrap().get(address).cloned() else {
+            return;
+        };
+        // Its icon notices the window is gone and goes away by itself.
+        if let Err(e) = self.args.restore(&*self.compositor, &window_info, None) {
+            error!(
+                "[Daemon] Failed to restore '{}': {:#}",
+                window_info.title, e
+            );
+        }
+    }
+
+    fn restore_all_and_quit(&self) {
+        let (restored, total) = self.restore_all();
+        info!(
+            "[Daemon] Restored {} of {} window(s), quitting.",
+            restored, total
+        );
+        self.quit.notify_one();
+    }
+}
+
+impl Daemon {
+    fn handle(self: &Arc<Self>, command: &str) -> String {
+        if let Some(address) = command.strip_prefix("minimize ") {
+            return match self.take_over(address.trim()) {
+                Ok(title) => format!("minimized '{}'\n", title),
+                Err(e) => format!("error: {:#}\n", e),
+            };
+        }
+        match command {
+            "metrics" => metrics::render(),
+            "restore-all" => {
+                let (restored, total) = self.restore_all();
+                self.quit.notify_one();
+                format!("restored {} of {} window(s), quitting\n", restored, total)
+            }
+            _ => format!("error: unknown command '{}'\n", command),
+        }
+    }
+
+    /// Restores every managed window to its original workspace, carrying on past
+    /// windows that are gone. Returns how many were restored, and of how many.
+    fn restore_all(&self) -> (usize, usize) {
+        let windows: Vec<WindowInfo> = self.managed.lock().unwrap().values().cloned().collect();
+        (restore_all(&*self.compositor, &windows), windows.len())
+    }
+
+    /// Stops serving all icons, the summary item included.
+    async fn unregister(&self) {
+        let items: Vec<dbus::ItemPaths> = self
+            .items
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, paths)| paths)
+            .collect();
+        for paths in items {
+            // Icons whose window is already gone may have removed themselves.
+            if let Err(e) = dbus::remove_item(&self.conn, &paths).await {
+                debug!("[Daemon] Icon at {} not removed: {:#}", paths.item, e);
+            }
+        }
+        if let Err(e) = dbus::remove_summary(&self.conn, &self.summary).await {
+            debug!("[Daemon] Summary item not removed: {:#}", e);
+        }
+    }
+
+    /// Tells the hosts of the summary item that a window came or went.
+    fn count_changed(self: &Arc<Self>) {
+        let daemon = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = dbus::summary_changed(&daemon.conn, &daemon.summary).await {
+                debug!("[Daemon] Summary item not updated: {:#}", e);
+            }
+        });
+    }
+
+    /// Minimizes the window and starts serving its icon. Returns the window title.
+    fn take_over(self: &Arc<Self>, address: &str) -> Result<String> {
+        if self.managed.lock().unwrap().contains_key(address) {
+            bail!("Window {} is already minimized", address);
+        }
+        let window_info = get_window_by_address(&*self.compositor, address)?;
+        crate::minimize(&*self.compositor, &window_info, &self.args)?;
+        if self.args.restore_on_exit != RestoreOnExit::Never && !self.args.dry_run {
+            crate::restore_on_panic(std::slice::from_ref(&window_info));
+        }
+        self.managed
+            .lock()
+            .unwrap()
+            .insert(window_info.address.clone(), window_info.clone());
+
+        let title = window_info.title.clone();
+        let paths =
+            dbus::ItemPaths::numbered(&self.args, self.next_item.fetch_add(1, Ordering::Relaxed));
+        self.items
+            .lock()
+            .unwrap()
+            .insert(window_info.address.clone(), paths.clone());
+        self.count_changed();
+        let daemon = Arc::clone(self);
+        tokio::spawn(async move {
+            let address = window_info.address.clone();
+            // Items sharing a connection are registered by their object path.
+            let service = paths.item.clone();
+            let conn = Arc::clone(&daemon.conn);
+            let compositor = Arc::clone(&daemon.compositor);
+            let args = Arc::clone(&daemon.args);
+            if let Err(e) = run_item(conn, compositor, window_info, args, paths, service).await {
+                error!("[Daemon] Tray icon for {} failed: {:#}", address, e);
+            }
+            daemon.managed.lock().unwrap().remove(&address);
+            daemon.items.lock().unwrap().remove(&address);
+            daemon.count_changed();
+        });
+        Ok(title)
+    }
+}
+
+/// Registers the summary item with the tray, and again whenever the tray restarts.
+async fn register_summary(daemon: Arc<Daemon>) {
+    let quirks = daemon.args.tray_quirks();
+    let service = daemon.summary.item.clone();
+    let timeout = Duration::from_millis(daemon.args.dbus_timeout);
+    let wait = Duration::from_millis(daemon.args.watcher_wait);
+    if let Err(e) =
+        dbus::register_when_available(&daemon.conn, &quirks.watcher_names, &service, timeout, wait)
+            .await
+    {
+        warn!("[Daemon] Could not register the summary item: {:#}", e);
+    }
+    dbus::watch_for_watcher_restarts(Arc::clone(&daemon.conn), quirks, service, timeout).await;
+}
+
+/// Runs the daemon until Ctrl+C or SIGTERM, which restores all windows it manages,
+/// or until it is asked to restore them all and quit.
+pub async fn run(args: Arc<Args>, compositor: Arc<dyn Compositor>) -> Result<()> {
+    let socket = match ControlSocket::from_systemd()? {
+        Some(socket) => {
+            info!("[Daemon] Listening on the socket passed by systemd");
+            socket
+        }
+        None => {
+            let path =
+                socket_path(&args).context("XDG_RUNTIME_DIR is not set. Pass --daemon-socket.")?;
+            let socket = ControlSocket::bind(&path).await?;
+            info!("[Daemon] Listening on {}", path.display());
+            socket
+        }
+    };
+
+    let bus_name = args.bus_name();
+    let conn = Arc::new(dbus::connect(&bus_name).await?);
+    info!("D-Bus service '{}' is running.", bus_name);
+
+    let daemon = Arc::new(Daemon {
+        summary: dbus::ItemPaths::single(&args),
+        args,
+        compositor,
+        conn,
+        managed: Mutex::new(HashMap::new()),
+        items: Mutex::new(HashMap::new()),
+        next_item: AtomicU32::new(1),
+        quit: Notify::new(),
+    });
+    dbus::add_summary(&daemon.conn, &daemon.summary, Arc::clone(&daemon) as _).await?;
+    tokio::spawn(register_summary(Arc::clone(&daemon)));
+    let handler_daemon = Arc::clone(&daemon);
+    tokio::spawn(socket.serve(move |command| handler_daemon.handle(command)));
+
+    let signal = tokio::select! {
+        signal = crate::shutdown_signal() => signal,
+        _ = daemon.quit.notified() => {
+            daemon.unregister().await;
+            info!("Exiting.");
+            return Ok(());
+        }
+    };
+    info!("Interrupted by {}. Restoring windows.", signal);
+    // Every icon restores its own window on the same signal; give them a moment to do so.
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while !daemon.managed.lock().unwrap().is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    info!("Exiting.");
+    Ok(())
+}
@@ -0,0 +1,1992 @@
+//! The D-Bus side of the tray icon: the StatusNotifierItem, its dbusmenu, and
+//! connection setup. All zbus specifics live in this module, so moving to a
+//! newer zbus version only has to touch this file.
+
+use crate::icon::{self, Pixmap};
+use crate::ipc::Compositor;
+use crate::quirks::{TrayQuirks, FREEDESKTOP_WATCHER, KDE_WATCHER};
+use crate::{
+    click_position, display_title, get_window_by_address, hyprctl, hyprctl_dispatch, metrics,
+    render_label, restore_window, toggle_special_workspace, Args, ClickAction, Monitor,
+    RestoreTarget, WindowInfo, Workspace, WorkspaceSummary,
+};
+use anyhow::{anyhow, Result};
+use futures_util::stream::StreamExt;
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{dbus_interface, ConnectionBuilder, Proxy};
+
+pub use zbus::{Connection, SignalContext};
+
+/// The object paths one tray item and its menu are served at.
+#[derive(Debug, Clone)]
+pub struct ItemPaths {
+    pub item: String,
+    pub menu: String,
+}
+
+impl ItemPaths {
+    /// The paths from `--item-path` and `--menu-path`, for a connection that
+    /// serves a single item.
+    pub fn single(args: &Args) -> ItemPaths {
+        ItemPaths {
+            item: args.item_path.clone(),
+            menu: args.menu_path.clone(),
+        }
+    }
+
+    /// Paths for the `n`th of several items sharing one connection.
+    pub fn numbered(args: &Args, n: u32) -> ItemPaths {
+        let below = |base: &str| format!("{}/{}", base.trim_end_matches('/'), n);
+        ItemPaths {
+            item: below(&args.item_path),
+            menu: below(&args.menu_path),
+        }
+    }
+}
+
+// --- Connection Setup ---
+
+/// Connects to the session bus under `bus_name`. Items are added with [`add_item`].
+pub async fn connect(bus_name: &str) -> Result<Connection> {
+    let connection = ConnectionBuilder::session()?
+        .name(bus_name)?
+        .build()
+        .await?;
+    Ok(connection)
+}
+
+/// Serves an item and its menu at the given paths.
+pub async fn add_item(
+    conn: &Connection,
+    paths: &ItemPaths,
+    item: StatusNotifierItem,
+    menu: DbusMenu,
+) -> Result<()> {
+    let server = conn.object_server();
+    server
+        .at(paths.item.as_str(), FreedesktopItem(item.clone()))
+        .await?;
+    server.at(paths.item.as_str(), item).await?;
+    server.at(paths.menu.as_str(), menu).await?;
+    Ok(())
+}
+
+/// Stops serving an item added with [`add_item`]. Hosts drop the icon once it is gone.
+pub async fn remove_item(conn: &Connection, paths: &ItemPaths) -> Result<()> {
+    let server = conn.object_server();
+    server
+        .remove::<StatusNotifierItem, _>(paths.item.as_str())
+        .await?;
+    server
+        .remove::<FreedesktopItem, _>(paths.item.as_str())
+        .await?;
+    server.remove::<DbusMenu, _>(paths.menu.as_str()).await?;
+    Ok(())
+}
+
+/// Emits an item signal under both interface names, see [`FreedesktopItem`].
+macro_rules! emit_item_signal {
+    ($signal:ident($ctxt:expr $(, $arg:expr)*)) => {
+        async {
+            StatusNotifierItem::$signal($ctxt $(, $arg)*).await?;
+            FreedesktopItem::$signal($ctxt $(, $arg)*).await
+        }
+    };
+}
+
+/// Applies `update` to both interfaces of the item at `path`.
+async fn update_item(
+    conn: &Connection,
+    path: &str,
+    update: impl Fn(&mut StatusNotifierItem),
+) -> Result<()> {
+    let server = conn.object_server();
+    let item = server.interface::<_, StatusNotifierItem>(path).await?;
+    update(&mut *item.get_mut().await);
+    let item = server.interface::<_, FreedesktopItem>(path).await?;
+    update(&mut item.get_mut().await.0);
+    Ok(())
+}
+
+/// Tells hosts to fetch the item's tooltip again.
+pub async fn tool_tip_changed(conn: &Connection, path: &str) -> Result<()> {
+    let ctxt = signal_context(conn, path)?;
+    emit_item_signal!(new_tool_tip(&ctxt)).await?;
+    Ok(())
+}
+
+/// The values of the SNI `Status` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemStatus {
+    Active,
+    /// Tucked away; most hosts hide passive items or move them to an overflow area.
+    Passive,
+    /// The window was flagged as urgent; hosts show the attention icon.
+    NeedsAttention,
+}
+
+impl ItemStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemStatus::Active => "Active",
+            ItemStatus::Passive => "Passive",
+            ItemStatus::NeedsAttention => "NeedsAttention",
+        }
+    }
+}
+
+/// Changes the status of the item at `path` and emits `NewStatus` if it actually changed.
+pub async fn set_status(
+    conn: &Connection,
+    path: &str,
+    status: &Mutex<ItemStatus>,
+    new_status: ItemStatus,
+) -> Result<()> {
+    {
+        let mut current = status.lock().unwrap();
+        if *current == new_status {
+            return Ok(());
+        }
+        *current = new_status;
+    }
+    let ctxt = signal_context(conn, path)?;
+    if new_status == ItemStatus::NeedsAttention {
+        emit_item_signal!(new_attention_icon(&ctxt)).await?;
+    }
+    emit_item_signal!(new_status(&ctxt, new_status.as_str())).await?;
+    Ok(())
+}
+
+/// Shows a new window title in the item's title, tooltip and menu labels.
+pub async fn update_title(conn: &Connection, paths: &ItemPaths, title: &str) -> Result<()> {
+    let server = conn.object_server();
+    update_item(conn, &paths.item, |item| {
+        item.window_info.title = title.to_string()
+    })
+    .await?;
+    let ctxt = signal_context(conn, &paths.item)?;
+    emit_item_signal!(new_title(&ctxt)).await?;
+    emit_item_signal!(new_tool_tip(&ctxt)).await?;
+
+    let menu = server.interface::<_, DbusMenu>(paths.menu.as_str()).await?;
+    let revision = {
+        let mut menu = menu.get_mut().await;
+        menu.window_info.title = title.to_string();
+        menu.revision += 1;
+        menu.revision
+    };
+    DbusMenu::layout_updated(menu.signal_context(), revision, 0).await?;
+    Ok(())
+}
+
+/// Points the item and its menu at the window's new address.
+pub async fn update_address(conn: &Connection, paths: &ItemPaths, address: &str) -> Result<()> {
+    update_item(conn, &paths.item, |item| {
+        item.window_info.address = address.to_string()
+    })
+    .await?;
+    let menu = conn
+        .object_server()
+        .interface::<_, DbusMenu>(paths.menu.as_str())
+        .await?;
+    menu.get_mut().await.window_info.address = address.to_string();
+    Ok(())
+}
+
+/// Returns the window as the item at `paths` currently knows it.
+pub async fn item_window(conn: &Connection, paths: &ItemPaths) -> Result<WindowInfo> {
+    let item = conn
+        .object_server()
+        .interface::<_, StatusNotifierItem>(paths.item.as_str())
+        .await?;
+    let window_info = item.get().await.window_info.clone();
+    Ok(window_info)
+}
+
+/// Shows the icon of a new window class, with `icon_pixmap` loaded for it.
+pub async fn update_class(
+    conn: &Connection,
+    paths: &ItemPaths,
+    class: &str,
+    icon_pixmap: Vec<Pixmap>,
+) -> Result<()> {
+    update_item(conn, &paths.item, |item| {
+        item.window_info.class = class.to_string();
+        item.icon_pixmap = icon_pixmap.clone();
+    })
+    .await?;
+    let ctxt = signal_context(conn, &paths.item)?;
+    emit_item_signal!(new_icon(&ctxt)).await?;
+    Ok(())
+}
+
+/// Returns a context for emitting signals from the object at `path`.
+pub fn signal_context<'a>(conn: &'a Connection, path: &'a str) -> Result<SignalContext<'a>> {
+    Ok(SignalContext::new(conn, path)?)
+}
+
+/// Registers our item with the StatusNotifierWatcher. `service` is either our bus
+/// name or, when several items share the connection, the item's object path.
+pub async fn register_with_watcher(
+    conn: &Connection,
+    watcher_name: &str,
+    service: &str,
+    timeout: Duration,
+) -> Result<()> {
+    // Watchers taking the freedesktop name serve the interface of the same name.
+    let interface = if watcher_name == FREEDESKTOP_WATCHER {
+        FREEDESKTOP_WATCHER
+    } else {
+        KDE_WATCHER
+    };
+    let register = async {
+        let watcher_proxy: Proxy<'_> = zbus::ProxyBuilder::new_bare(conn)
+            .interface(interface)?
+            .path("/StatusNotifierWatcher")?
+            .destination(watcher_name)?
+            .build()
+            .await?;
+        watcher_proxy
+            .call_method("RegisterStatusNotifierItem", &(service,))
+            .await?;
+        Ok(())
+    };
+    // A hung watcher would otherwise block startup forever.
+    tokio::time::timeout(timeout, register).await.map_err(|_| {
+        anyhow!(
+            "'{}' did not answer within {}ms",
+            watcher_name,
+            timeout.as_millis()
+        )
+    })?
+}
+
+/// How often to check for the watcher while waiting for it to appear.
+const WATCHER_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Registers with every StatusNotifierWatcher of `watcher_names` on the bus,
+/// waiting up to `wait` for one to appear and retrying failed registrations in
+/// the meantime. Succeeds as soon as one of them accepted the item.
+pub async fn register_when_available(
+    conn: &Connection,
+    watcher_names: &[String],
+    service: &str,
+    timeout: Duration,
+    wait: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + wait;
+    let dbus_proxy = zbus::fdo::DBusProxy::new(conn).await?;
+    loop {
+        let mut registered = false;
+        let mut error = None;
+        for watcher_name in watcher_names {
+            let name = zbus::names::BusName::try_from(watcher_name.as_str())?;
+            // Treat a failed lookup as present and let the registration report the problem.
+            let available = dbus_proxy.name_has_owner(name).await.unwrap_or(true);
+            if !available {
+                continue;
+            }
+            match register_with_watcher(conn, watcher_name, service, timeout).await {
+                Ok(()) => {
+                    debug!("[Watcher] Registered with '{}'.", watcher_name);
+                    registered = true;
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        if registered {
+            return Ok(());
+        }
+        let error = error.unwrap_or_else(|| {
+            anyhow!(
+                "None of {} appeared on the bus within {}ms",
+                watcher_names.join(", "),
+                wait.as_millis()
+            )
+        });
+        if Instant::now() >= deadline {
+            return Err(error);
+        }
+        debug!("[Watcher] Not registered yet ({}), retrying.", error);
+        tokio::time::sleep(WATCHER_RETRY_INTERVAL).await;
+    }
+}
+
+/// Re-registers our item whenever the StatusNotifierWatcher gets a new owner,
+/// e.g. because Waybar was restarted. Runs until the bus connection goes away.
+pub async fn watch_for_watcher_restarts(
+    conn: Arc<Connection>,
+    quirks: TrayQuirks,
+    service: String,
+    timeout: Duration,
+) {
+    let dbus_proxy = match zbus::fdo::DBusProxy::new(&conn).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("[Watcher] Failed to connect to D-Bus proxy: {}", e);
+            return;
+        }
+    };
+
+    let mut owner_changes = match dbus_proxy.receive_name_owner_changed().await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[Watcher] Failed to listen for owner changes: {}", e);
+            return;
+        }
+    };
+
+    info!(
+        "[Watcher] Watching for {} restarts...",
+        quirks.watcher_names.join(", ")
+    );
+
+    while let Some(signal) = owner_changes.next().await {
+        if let Ok(args) = signal.args() {
+            // Only a real change of owner means a new watcher that does not know us yet.
+            let new_owner = args.new_owner().as_ref().map(|o| o.as_str());
+            let old_owner = args.old_owner().as_ref().map(|o| o.as_str());
+            let Some(watcher_name) = quirks
+                .watcher_names
+                .iter()
+                .find(|n| args.name() == n.as_str())
+            else {
+                continue;
+            };
+            if new_owner.is_some() && new_owner != old_owner {
+                info!("[Watcher] Tray service detected. Re-registering icon.");
+                // Give the watcher a moment to get ready
+                tokio::time::sleep(quirks.registration_delay).await;
+                match register_with_watcher(&conn, watcher_name, &service, timeout).await {
+                    Ok(()) => metrics::DBUS_RECONNECTS_TOTAL.inc(),
+                    Err(e) => error!("[Watcher] Failed to re-register icon: {}", e),
+                }
+            }
+        }
+    }
+}
+
+// --- Desktop Notifications ---
+
+async fn notifications_proxy(conn: &Connection) -> zbus::Result<Proxy<'_>> {
+    zbus::ProxyBuilder::new_bare(conn)
+        .interface("org.freedesktop.Notifications")?
+        .path("/org/freedesktop/Notifications")?
+        .destination("org.freedesktop.Notifications")?
+        .build()
+        .await
+}
+
+/// Shows a transient desktop notification through `org.freedesktop.Notifications`
+/// and returns its id. `icon` is an icon name, e.g. the window class, and
+/// `actions` are the (key, label) pairs of its buttons.
+pub async fn notify(
+    conn: &Connection,
+    icon: &str,
+    summary: &str,
+    actions: &[(&str, &str)],
+    timeout: Duration,
+) -> Result<u32> {
+    let notify = async {
+        let proxy = notifications_proxy(conn).await?;
+        let mut hints: HashMap<&str, Value<'_>> = HashMap::new();
+        // Don't keep the notification around in the notification history.
+        hints.insert("transient", Value::from(true));
+        let actions: Vec<&str> = actions
+            .iter()
+            .flat_map(|(key, label)| [*key, *label])
+            .collect();
+        let id: u32 = proxy
+            .call(
+                "Notify",
+                &(
+                    "hyprland-minimizer",
+                    0u32,
+                    icon,
+                    summary,
+                    "",
+                    actions,
+                    hints,
+                    -1i32,
+                ),
+            )
+            .await?;
+        Ok(id)
+    };
+    tokio::time::timeout(timeout, notify).await.map_err(|_| {
+        anyhow!(
+            "the notification daemon did not answer within {}ms",
+            timeout.as_millis()
+        )
+    })?
+}
+
+/// Asks `question` in a notification with Close and Cancel buttons. Returns true
+/// only if the user picked Close; dismissing the notification counts as Cancel.
+pub async fn confirm_close(
+    conn: &Connection,
+    icon: &str,
+    question: &str,
+    timeout: Duration,
+) -> Result<bool> {
+    // Listen before asking, so a quick answer is not missed.
+    let proxy = notifications_proxy(conn).await?;
+    let mut invoked = proxy.receive_signal("ActionInvoked").await?;
+    let mut closed = proxy.receive_signal("NotificationClosed").await?;
+    let id = notify(
+        conn,
+        icon,
+        question,
+        &[("close", "Close"), ("cancel", "Cancel")],
+        timeout,
+    )
+    .await?;
+    loop {
+        tokio::select! {
+            Some(msg) = invoked.next() => {
+                let (notification, action): (u32, String) = msg.body()?;
+                if notification == id {
+                    return Ok(action == "close");
+                }
+            }
+            Some(msg) = closed.next() => {
+                let (notification, _reason): (u32, u32) = msg.body()?;
+                if notification == id {
+                    return Ok(false);
+                }
+            }
+            else => anyhow::bail!("Lost the connection to the notification daemon"),
+        }
+    }
+}
+
+/// Closes the window right away, or with `--confirm-close` hands the request to
+/// the task asking for confirmation. Returns true if the window was closed.
+fn close_window(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+    args: &Args,
+    close_request: &Notify,
+) -> Result<bool> {
+    if args.confirm_close {
+        close_request.notify_one();
+        return Ok(false);
+    }
+    hyprctl_dispatch(
+        compositor,
+        &format!("closewindow address:{}", window_info.address),
+    )?;
+    Ok(true)
+}
+
+// --- D-Bus Menu Implementation ---
+
+/// Properties of a single dbusmenu item.
+type MenuProperties = HashMap<String, Value<'static>>;
+
+/// A dbusmenu layout node: (id, properties, children).
+type MenuLayout = (i32, MenuProperties, Vec<Value<'static>>);
+
+pub struct DbusMenu {
+    window_info: WindowInfo,
+    args: Arc<Args>,
+    compositor: Arc<dyn Compositor>,
+    exit_notify: Arc<Notify>,
+    close_request: Arc<Notify>,
+    /// Bumped whenever the labels change, so hosts fetch the layout again.
+    revision: u32,
+    /// The items as of the last `GetLayout`, to tell whether the menu went stale.
+    served: Vec<(i32, MenuProperties)>,
+    /// What the items show about Hyprland, as of the last layout request.
+    state: MenuState,
+    /// Where clicks run, see [`run_blocking`].
+    runtime: Option<Handle>,
+}
+
+/// What the menu shows about Hyprland. It is fetched before the layout is
+/// served, on the blocking pool like clicks (see [`run_blocking`]), and the
+/// items are built from it.
+#[derive(Default)]
+struct MenuState {
+    /// The window as Hyprland reports it now, for its title.
+    window: Option<WindowInfo>,
+    /// The id of the active workspace, to grey out a redundant restore.
+    active_workspace: Option<i32>,
+    /// The numbered workspaces, or none if they could not be listed.
+    workspaces: Option<Vec<WorkspaceSummary>>,
+}
+
+impl MenuState {
+    fn fetch(compositor: &dyn Compositor, address: &str) -> MenuState {
+        let window = get_window_by_address(compositor, address)
+            .map_err(|e| debug!("[D-Bus Menu] Failed to refresh the window: {}", e))
+            .ok();
+        let active_workspace = hyprctl::<Workspace>(compositor, "activeworkspace")
+            .map(|active| active.id)
+            .ok();
+        let workspaces = hyprctl::<Vec<WorkspaceSummary>>(compositor, "workspaces")
+            .map(|mut workspaces| {
+                workspaces.retain(|w| w.id > 0);
+                workspaces.sort_by_key(|w| w.id);
+                workspaces
+            })
+            .map_err(|e| error!("[D-Bus Menu] Failed to list workspaces: {}", e))
+            .ok();
+        MenuState {
+            window,
+            active_workspace,
+            workspaces,
+        }
+    }
+}
+
+/// What a click on the item or its menu acts on, taken out of the D-Bus object
+/// so the action can run away from the connection's executor.
+struct WindowActions {
+    window_info: WindowInfo,
+    args: Arc<Args>,
+    compositor: Arc<dyn Compositor>,
+    exit_notify: Arc<Notify>,
+    close_request: Arc<Notify>,
+    learned_target: Option<i32>,
+}
+
+/// Runs a click's action on the runtime's blocking pool: restoring talks to
+/// Hyprland and may wait out retries, which would stall every other D-Bus call
+/// on the executor. Outside a runtime (the tests) the action runs right away.
+fn run_blocking(runtime: &Option<Handle>, action: impl FnOnce() + Send + 'static) {
+    match runtime {
+        Some(runtime) => drop(runtime.spawn_blocking(action)),
+        None => action(),
+    }
+}
+
+/// Menu item ids. The root node is always 0.
+const MENU_OPEN: i32 = 1;
+const MENU_OPEN_ORIGINAL: i32 = 2;
+const MENU_CLOSE: i32 = 3;
+const MENU_MOVE_TO: i32 = 4;
+const MENU_PIN: i32 = 5;
+const MENU_PEEK: i32 = 6;
+const MENU_SEPARATOR: i32 = 7;
+/// The "Move to workspace" entries use this plus the workspace id.
+const MENU_WORKSPACE_BASE: i32 = 100;
+
+/// A menu item with the items of its submenu, if it has one.
+struct MenuEntry {
+    id: i32,
+    props: MenuProperties,
+    children: Vec<MenuEntry>,
+}
+
+impl MenuEntry {
+    fn new(id: i32, props: MenuProperties) -> MenuEntry {
+        MenuEntry {
+            id,
+            props,
+            children: Vec::new(),
+        }
+    }
+
+    /// Turns the entry into a dbusmenu layout node.
+    fn into_layout(self) -> Value<'static> {
+        let children: Vec<Value> = self
+            .children
+            .into_iter()
+            .map(MenuEntry::into_layout)
+            .collect();
+        Value::from((self.id, self.props, children))
+    }
+
+    /// Collects the entry and all entries below it.
+    fn flatten(self, into: &mut Vec<(i32, MenuProperties)>) {
+        into.push((self.id, self.props));
+        for child in self.children {
+            child.flatten(into);
+        }
+    }
+}
+
+/// Builds the properties of a plain clickable menu item.
+fn standard_item(label: String) -> MenuProperties {
+    let mut props = HashMap::new();
+    props.insert("type".to_string(), Value::from("standard"));
+    props.insert("label".to_string(), Value::from(label));
+    props.insert("enabled".to_string(), Value::from(true));
+    props.insert("visible".to_string(), Value::from(true));
+    props
+}
+
+/// Builds the properties of a separator line.
+fn separator_item() -> MenuProperties {
+    let mut props = HashMap::new();
+    props.insert("type".to_string(), Value::from("separator"));
+    props.insert("visible".to_string(), Value::from(true));
+    props
+}
+
+/// Greys out an item so it cannot be clicked.
+fn disabled_item(mut props: MenuProperties) -> MenuProperties {
+    props.insert("enabled".to_string(), Value::from(false));
+    props
+}
+
+/// Builds the properties of a checkmark item that is checked when `checked` is set.
+fn toggle_item(label: String, checked: bool) -> MenuProperties {
+    let mut props = standard_item(label);
+    props.insert("toggle-type".to_string(), Value::from("checkmark"));
+    props.insert("toggle-state".to_string(), Value::from(i32::from(checked)));
+    props
+}
+
+impl DbusMenu {
+    pub fn new(
+        window_info: WindowInfo,
+        args: Arc<Args>,
+        compositor: Arc<dyn Compositor>,
+        exit_notify: Arc<Notify>,
+        close_request: Arc<Notify>,
+    ) -> DbusMenu {
+        DbusMenu {
+            window_info,
+            args,
+            compositor,
+            exit_notify,
+            close_request,
+            revision: 2,
+            served: Vec::new(),
+            state: MenuState::default(),
+            runtime: Handle::try_current().ok(),
+        }
+    }
+
+    fn actions(&self) -> WindowActions {
+        WindowActions {
+            window_info: self.window_info.clone(),
+            args: Arc::clone(&self.args),
+            compositor: Arc::clone(&self.compositor),
+            exit_notify: Arc::clone(&self.exit_notify),
+            close_request: Arc::clone(&self.close_request),
+            learned_target: None,
+        }
+    }
+
+    /// Fetches the [`MenuState`] away from the connection's executor, or right
+    /// away outside a runtime (the tests), and picks up title changes the
+    /// window watcher has not passed on yet.
+    async fn fetch_state(&mut self) {
+        let compositor = Arc::clone(&self.compositor);
+        let address = self.window_info.address.clone();
+        let fetch = move || MenuState::fetch(&*compositor, &address);
+        self.state = match &self.runtime {
+            Some(runtime) => runtime.spawn_blocking(fetch).await.unwrap_or_default(),
+            None => fetch(),
+        };
+        if let Some(window) = &self.state.window {
+            self.window_info.title = display_title(window);
+        }
+    }
+
+    /// Fetches the state and returns the ids of the items that differ from the
+    /// last layout served, bumping the revision if there are any.
+    async fn refresh(&mut self) -> Vec<i32> {
+        self.fetch_state().await;
+        let items = self.all_items();
+        let mut changed: Vec<i32> = items
+            .iter()
+            .filter(|item| !self.served.contains(item))
+            .map(|(id, _)| *id)
+            .collect();
+        changed.extend(
+            self.served
+                .iter()
+                .filter(|(id, _)| !items.iter().any(|(other, _)| other == id))
+                .map(|(id, _)| *id),
+        );
+        if !changed.is_empty() {
+            self.revision += 1;
+        }
+        changed
+    }
+
+    /// Returns the top-level menu items, in display order. The dbusmenu spec has
+    /// no default item property, so the restore item marks the default action by
+    /// always coming first.
+    fn items(&self) -> Vec<MenuEntry> {
+        let label = |template: &str| standard_item(render_label(template, &self.window_info));
+        if self.args.compact_menu {
+            return vec![
+                MenuEntry::new(MENU_OPEN, label(&self.args.menu_restore_label)),
+                MenuEntry::new(MENU_SEPARATOR, separator_item()),
+                MenuEntry::new(MENU_CLOSE, label(&self.args.menu_close_label)),
+            ];
+        }
+        // Opening on the original workspace does nothing new while that is the current one.
+        let on_original = self.state.active_workspace == Some(self.window_info.workspace.id);
+        let mut original = label(&self.args.menu_original_label);
+        if on_original {
+            original = disabled_item(original);
+        }
+        let mut items = vec![
+            MenuEntry::new(MENU_OPEN, label(&self.args.menu_open_label)),
+            MenuEntry::new(MENU_OPEN_ORIGINAL, original),
+            MenuEntry::new(MENU_PEEK, standard_item("Peek".to_string())),
+        ];
+        if let Some(workspaces) = &self.state.workspaces {
+            let mut props = standard_item("Move to workspace".to_string());
+            props.insert("children-display".to_string(), Value::from("submenu"));
+            items.push(MenuEntry {
+                id: MENU_MOVE_TO,
+                props,
+                children: workspace_entries(workspaces),
+            });
+        }
+        items.push(MenuEntry::new(
+            MENU_PIN,
+            toggle_item("Pin to all workspaces".to_string(), self.window_info.pinned),
+        ));
+        items.push(MenuEntry::new(MENU_SEPARATOR, separator_item()));
+        items.push(MenuEntry::new(
+            MENU_CLOSE,
+            label(&self.args.menu_close_label),
+        ));
+        items
+    }
+
+    /// Returns the ids and properties of all menu items, submenus included.
+    fn all_items(&self) -> Vec<(i32, MenuProperties)> {
+        let mut all = Vec::new();
+        for item in self.items() {
+            item.flatten(&mut all);
+        }
+        all
+    }
+}
+
+/// Returns one entry per workspace for the "Move to workspace" submenu,
+/// labeled with the workspace's name.
+fn workspace_entries(workspaces: &[WorkspaceSummary]) -> Vec<MenuEntry> {
+    workspaces
+        .iter()
+        .map(|w| MenuEntry::new(MENU_WORKSPACE_BASE + w.id, standard_item(w.name.clone())))
+        .collect()
+}
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl DbusMenu {
+    /// Returns the menu layout, with the state fetched anew.
+    async fn get_layout(
+        &mut self,
+        _parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> (u32, MenuLayout) {
+        debug!("[D-Bus Menu] GetLayout called.");
+
+        self.fetch_state().await;
+        self.served = self.all_items();
+        let children = self
+            .items()
+            .into_iter()
+            .map(MenuEntry::into_layout)
+            .collect();
+
+        // The root of the menu layout
+        let mut root_props = HashMap::new();
+        root_props.insert("children-display".to_string(), Value::from("submenu"));
+
+        let root_layout = (
+            0i32, // Root node ID is always 0
+            root_props, children,
+        );
+
+        let revision = self.revision;
+        debug!(
+            "[D-Bus Menu] Serving layout revision {}: {:?}",
+            revision, root_layout
+        );
+        (revision, root_layout)
+    }
+
+    /// Returns the properties for a group of menu items, as of the last layout.
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, MenuProperties)> {
+        debug!("[D-Bus Menu] GetGroupProperties called for IDs: {:?}", ids);
+        let result: Vec<_> = self
+            .all_items()
+            .into_iter()
+            .filter(|(id, _)| ids.contains(id))
+            .collect();
+        debug!("[D-Bus Menu] Returning properties: {:?}", result);
+        result
+    }
+
+    /// Handles a batch of click events. This is called by Waybar instead of the singular `Event`.
+    fn event_group(&self, events: Vec<(i32, String, Value<'_>, u32)>) {
+        debug!(
+            "[D-Bus Menu] EventGroup received with {} events",
+            events.len()
+        );
+        for (id, event_id, data, timestamp) in events {
+            self.event(id, &event_id, data, timestamp);
+        }
+    }
+
+    /// Handles a single click event on a menu item.
+    fn event(&self, id: i32, event_id: &str, _data: Value<'_>, _timestamp: u32) {
+        debug!(
+            "[D-Bus Menu] Event received: id='{}', event_id='{}'",
+            id, event_id
+        );
+        if event_id == "clicked" {
+            let actions = self.actions();
+            run_blocking(&self.runtime, move || actions.menu_clicked(id));
+        }
+    }
+
+    /// Handles a batch of "about to show" requests. Returns the ids among them
+    /// whose items changed since the last layout, and no errors.
+    async fn about_to_show_group(&mut self, ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        debug!("[D-Bus Menu] AboutToShowGroup received for IDs: {:?}", ids);
+        let changed = self.refresh().await;
+        // A change to any item changes the root's layout.
+        let updates = ids
+            .into_iter()
+            .filter(|id| changed.contains(id) || (*id == 0 && !changed.is_empty()))
+            .collect();
+        (updates, vec![])
+    }
+
+    /// Called before a menu is shown. Returns true if the host should fetch the layout again.
+    async fn about_to_show(&mut self, id: i32) -> bool {
+        debug!("[D-Bus Menu] AboutToShow received for ID {}", id);
+        !self.refresh().await.is_empty()
+    }
+
+    #[dbus_interface(signal)]
+    async fn layout_updated(
+        ctxt: &SignalContext<'_>,
+        revision: u32,
+        parent: i32,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+}
+
+// --- Status Notifier Item (Tray Icon) Implementation ---
+
+/// An SNI tooltip: (icon name, icon pixmaps, title, description).
+type ToolTip = (String, Vec<Pixmap>, String, String);
+
+#[derive(Clone)]
+pub struct StatusNotifierItem {
+    pub window_info: WindowInfo,
+    pub args: Arc<Args>,
+    pub compositor: Arc<dyn Compositor>,
+    pub quirks: TrayQuirks,
+    /// The workspace learned from earlier manual restores, if `--learn-restore-target` is set.
+    pub learned_target: Option<i32>,
+    /// When the window gets closed by `--auto-close-after`.
+    pub auto_close_at: Option<Instant>,
+    pub status: Arc<Mutex<ItemStatus>>,
+    /// The window's icon, for hosts that can't find `IconName` in their theme.
+    pub icon_pixmap: Vec<Pixmap>,
+    /// The name of the window's monitor for the tooltip, see [`monitor_name`].
+    pub monitor_name: String,
+    /// Where the item's menu is served, see [`ItemPaths`].
+    pub menu_path: String,
+    pub exit_notify: Arc<Notify>,
+    /// Asks the task confirming closes (`--confirm-close`) for a confirmation.
+    pub close_request: Arc<Notify>,
+    /// The latest token a host sent with `ProvideXdgActivationToken`.
+    pub activation_token: Arc<Mutex<Option<String>>>,
+    /// Where clicks run, see [`run_blocking`].
+    pub runtime: Option<Handle>,
+}
+
+/// The same item under the `org.freedesktop.StatusNotifierItem` name, served
+/// next to it at the item's path. Both copies get every update.
+pub struct FreedesktopItem(pub StatusNotifierItem);
+
+impl Deref for FreedesktopItem {
+    type Target = StatusNotifierItem;
+
+    fn deref(&self) -> &StatusNotifierItem {
+        &self.0
+    }
+}
+
+impl DerefMut for FreedesktopItem {
+    fn deref_mut(&mut self) -> &mut StatusNotifierItem {
+        &mut self.0
+    }
+}
+
+impl StatusNotifierItem {
+    fn actions(&self) -> WindowActions {
+        WindowActions {
+            window_info: self.window_info.clone(),
+            args: Arc::clone(&self.args),
+            compositor: Arc::clone(&self.compositor),
+            exit_notify: Arc::clone(&self.exit_notify),
+            close_request: Arc::clone(&self.close_request),
+            learned_target: self.learned_target,
+        }
+    }
+
+    /// Runs the action configured for a click on the icon.
+    fn run_click_action(&self, action: ClickAction, click: Option<(i32, i32)>) {
+        // Tokens belong to the click they came with. Hyprland focuses windows on
+        // dispatch without focus stealing prevention, so there is nothing to hand
+        // it to; restoring works the same without one.
+        if let Some(token) = self.activation_token.lock().unwrap().take() {
+            debug!("[D-Bus] Click came with activation token '{}'", token);
+        }
+        let actions = self.actions();
+        run_blocking(&self.runtime, move || actions.click(action, click));
+    }
+}
+
+impl WindowActions {
+    /// Runs the action of the menu item with the given id.
+    fn menu_clicked(&self, id: i32) {
+        let compositor = &*self.compositor;
+        let res = match id {
+            MENU_OPEN => {
+                // Open on current workspace, or wherever --restore-to says in the compact menu
+                info!("[D-Bus Menu] 'Open' action triggered.");
+                if self.args.compact_menu {
+                    self.args.restore(compositor, &self.window_info, None)
+                } else {
+                    RestoreTarget::Current
+                        .resolve(compositor, &self.window_info, None)
+                        .and_then(|id| {
+                            restore_window(compositor, &self.window_info, id, &self.args)
+                        })
+                }
+            }
+            MENU_OPEN_ORIGINAL => {
+                // Open on original workspace
+                info!("[D-Bus Menu] 'Open on original workspace' action triggered.");
+                restore_window(
+                    compositor,
+                    &self.window_info,
+                    self.window_info.workspace.id,
+                    &self.args,
+                )
+            }
+            MENU_PEEK => {
+                // Slide in the special workspace; the window stays minimized.
+                info!("[D-Bus Menu] 'Peek' action triggered.");
+                if let Err(e) = toggle_special_workspace(compositor, self.args.rename_workspace) {
+                    error!("Failed to show the special workspace: {}", e);
+                }
+                return;
+            }
+            MENU_CLOSE => {
+                // Close the window
+                info!("[D-Bus Menu] 'Close' action triggered.");
+                match close_window(
+                    compositor,
+                    &self.window_info,
+                    &self.args,
+                    &self.close_request,
+                ) {
+                    Ok(true) => Ok(()),
+                    // The icon stays until the user confirmed.
+                    Ok(false) => return,
+                    Err(e) => Err(e),
+                }
+            }
+            MENU_PIN if self.window_info.pinned => {
+                // Back to the workspace it was pinned on, without pinning it again.
+                info!("[D-Bus Menu] 'Unpin' action triggered.");
+                let unpinned = WindowInfo {
+                    pinned: false,
+                    ..self.window_info.clone()
+                };
+                restore_window(compositor, &unpinned, unpinned.workspace.id, &self.args)
+            }
+            MENU_PIN => {
+                info!("[D-Bus Menu] 'Pin to all workspaces' action triggered.");
+                RestoreTarget::Current
+                    .resolve(compositor, &self.window_info, None)
+                    .and_then(|id| restore_window(compositor, &self.window_info, id, &self.args))
+                    .and_then(|()| {
+                        // Hyprland only pins floating windows.
+                        let address = &self.window_info.address;
+                        if !self.window_info.floating {
+                            hyprctl_dispatch(
+                                compositor,
+                                &format!("setfloating address:{}", address),
+                            )?;
+                        }
+                        hyprctl_dispatch(compositor, &format!("pin address:{}", address))
+                    })
+            }
+            id if id > MENU_WORKSPACE_BASE => {
+                let workspace_id = id - MENU_WORKSPACE_BASE;
+                info!(
+                    "[D-Bus Menu] 'Move to workspace {}' action triggered.",
+                    workspace_id
+                );
+                restore_window(compositor, &self.window_info, workspace_id, &self.args)
+            }
+            _ => {
+                info!("[D-Bus Menu] Clicked on unknown item id: {}", id);
+                return;
+            }
+        };
+
+        if let Err(e) = res {
+            error!("Failed to execute hyprctl dispatch from menu: {}", e);
+        }
+
+        self.exit_notify.notify_one();
+    }
+
+    /// Runs a click action on the icon.
+    fn click(&self, action: ClickAction, click: Option<(i32, i32)>) {
+        let compositor = &*self.compositor;
+        let result = match action {
+            ClickAction::Nothing => return,
+            ClickAction::Restore => match self.learned_target {
+                Some(id) => {
+                    info!("[D-Bus] Restoring to learned workspace {}", id);
+                    restore_window(compositor, &self.window_info, id, &self.args)
+                }
+                None => self.args.restore(compositor, &self.window_info, click),
+            },
+            ClickAction::RestoreCurrent => RestoreTarget::Current
+                .resolve(compositor, &self.window_info, click)
+                .and_then(|id| restore_window(compositor, &self.window_info, id, &self.args)),
+            ClickAction::RestoreOriginal => restore_window(
+                compositor,
+                &self.window_info,
+                self.window_info.workspace.id,
+                &self.args,
+            ),
+            ClickAction::Close => {
+                match close_window(
+                    compositor,
+                    &self.window_info,
+                    &self.args,
+                    &self.close_request,
+                ) {
+                    Ok(true) => Ok(()),
+                    // The icon stays until the user confirmed.
+                    Ok(false) => return,
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to execute click action: {}", e);
+        }
+        self.exit_notify.notify_one();
+    }
+
+    /// Moves the window one workspace up or down from the active one.
+    fn scroll(&self, delta: i32) {
+        let compositor = &*self.compositor;
+        let result = RestoreTarget::Current
+            .resolve(compositor, &self.window_info, None)
+            .and_then(|active| {
+                // Only numbered workspaces are valid targets.
+                let target = active.saturating_add(delta.signum()).max(1);
+                info!("[D-Bus] Scrolled onto workspace {}", target);
+                restore_window(compositor, &self.window_info, target, &self.args)
+            });
+        if let Err(e) = result {
+            error!("Failed to execute scroll action: {}", e);
+        }
+        self.exit_notify.notify_one();
+    }
+}
+
+/// Returns the name of the monitor with the given id, or the id if it is not
+/// connected. Items look it up once, as the tooltip is fetched on the
+/// connection's executor, where Hyprland requests would stall every call.
+pub fn monitor_name(compositor: &dyn Compositor, id: i32) -> String {
+    hyprctl::<Vec<Monitor>>(compositor, "monitors")
+        .ok()
+        .and_then(|monitors| monitors.into_iter().find(|m| m.id == id))
+        .map(|m| m.name)
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Implements the SNI interface `$name` on `$item`. KDE hosts use the
+/// `org.kde` name, most others the `org.freedesktop` one, see [`FreedesktopItem`].
+macro_rules! status_notifier_item {
+    ($name:tt, $item:ident) => {
+        #[dbus_interface(name = $name)]
+        impl $item {
+            // --- Properties ---
+            #[dbus_interface(property)]
+            fn category(&self) -> &str {
+                "ApplicationStatus"
+            }
+
+            #[dbus_interface(property)]
+            fn id(&self) -> &str {
+                &self.window_info.class
+            }
+
+            #[dbus_interface(property)]
+            fn title(&self) -> &str {
+                &self.window_info.title
+            }
+
+            #[dbus_interface(property)]
+            fn status(&self) -> &str {
+                self.status.lock().unwrap().as_str()
+            }
+
+            #[dbus_interface(property)]
+            fn icon_name(&self) -> &str {
+                // Hosts prefer a name they find in their theme over the pixmap.
+                match self.args.icon {
+                    Some(_) => "",
+                    None => self.window_info.icon_class(),
+                }
+            }
+
+            #[dbus_interface(property)]
+            fn icon_pixmap(&self) -> Vec<Pixmap> {
+                self.icon_pixmap.clone()
+            }
+
+            /// Where hosts look for `IconName` besides the theme; empty without `--icon-theme-path`.
+            #[dbus_interface(property)]
+            fn icon_theme_path(&self) -> String {
+                self.args
+                    .icon_theme_path
+                    .as_ref()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default()
+            }
+
+            /// The window's own icon: the `NeedsAttention` status is what makes hosts
+            /// highlight it.
+            #[dbus_interface(property)]
+            fn attention_icon_name(&self) -> &str {
+                self.icon_name()
+            }
+
+            /// The `--icon` image, which has no name, or the window's pixmap.
+            #[dbus_interface(property)]
+            fn attention_icon_pixmap(&self) -> Vec<Pixmap> {
+                self.icon_pixmap.clone()
+            }
+
+            #[dbus_interface(property)]
+            fn tool_tip(&self) -> ToolTip {
+                let mut lines = vec![
+                    format!("Workspace: {}", self.window_info.workspace.id),
+                    format!("Class: {}", self.window_info.class),
+                    format!("Monitor: {}", self.monitor_name),
+                ];
+                if let Some(deadline) = self.auto_close_at {
+                    let remaining = deadline.saturating_duration_since(Instant::now()).as_secs();
+                    lines.push(format!(
+                        "Closes in {}:{:02}",
+                        remaining / 60,
+                        remaining % 60
+                    ));
+                }
+                let title = if self.args.show_xwayland && self.window_info.xwayland {
+                    format!("{} (X11)", self.window_info.title)
+                } else {
+                    self.window_info.title.clone()
+                };
+                (
+                    self.icon_name().to_string(),
+                    self.icon_pixmap.clone(),
+                    title,
+                    lines.join("\n"),
+                )
+            }
+
+            #[dbus_interface(property)]
+            fn item_is_menu(&self) -> bool {
+                self.quirks.item_is_menu
+            }
+
+            #[dbus_interface(property)]
+            fn menu(&self) -> ObjectPath<'_> {
+                ObjectPath::try_from(self.menu_path.as_str()).unwrap()
+            }
+
+            // --- Methods ---
+            /// Receives the activation token of the click hosts are about to report.
+            fn provide_xdg_activation_token(&self, token: String) {
+                debug!("[D-Bus] ProvideXdgActivationToken called");
+                *self.activation_token.lock().unwrap() = Some(token);
+            }
+
+            fn activate(&self, x: i32, y: i32) {
+                debug!("[D-Bus] Activate called (left-click) at {},{}", x, y);
+                self.run_click_action(self.args.left_action, click_position(x, y));
+            }
+
+            fn secondary_activate(&self, x: i32, y: i32) {
+                debug!(
+                    "[D-Bus] SecondaryActivate called (middle-click) at {},{}",
+                    x, y
+                );
+                self.run_click_action(self.args.middle_action, click_position(x, y));
+            }
+
+            /// Moves the window to the workspace after (or before) the active one.
+            fn scroll(&self, delta: i32, orientation: &str) {
+                debug!("[D-Bus] Scroll called: {} {}", delta, orientation);
+                if delta == 0 || !orientation.eq_ignore_ascii_case("vertical") {
+                    return;
+                }
+                let actions = self.actions();
+                run_blocking(&self.runtime, move || actions.scroll(delta));
+            }
+
+            // --- Signals ---
+            #[dbus_interface(signal)]
+            async fn new_title(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn new_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn new_attention_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            pub async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            pub async fn new_status(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+        }
+    };
+}
+
+status_notifier_item!("org.kde.StatusNotifierItem", StatusNotifierItem);
+status_notifier_item!("org.freedesktop.StatusNotifierItem", FreedesktopItem);
+
+// --- Daemon Summary Item ---
+
+/// What the daemon's [`SummaryItem`] lists and does.
+pub trait Summary: Send + Sync {
+    /// The windows the daemon manages.
+    fn windows(&self) -> Vec<WindowInfo>;
+    /// Restores one of them the way a left-click on its own icon does.
+    fn restore(&self, address: &str);
+    /// Restores all of them to their original workspaces and shuts the daemon down.
+    fn restore_all_and_quit(&self);
+}
+
+/// The daemon's own tray item: one icon for all the windows it manages, with
+/// their number as a badge and a menu to restore each of them. Only this item
+/// carries the badge; the icons of the windows don't.
+#[derive(Clone)]
+pub struct SummaryItem {
+    pub summary: Arc<dyn Summary>,
+    pub menu_path: String,
+}
+
+/// The summary item under the `org.freedesktop.StatusNotifierItem` name.
+pub struct FreedesktopSummaryItem(pub SummaryItem);
+
+impl Deref for FreedesktopSummaryItem {
+    type Target = SummaryItem;
+
+    fn deref(&self) -> &SummaryItem {
+        &self.0
+    }
+}
+
+/// The menu of the [`SummaryItem`]: one entry per managed window.
+pub struct SummaryMenu {
+    summary: Arc<dyn Summary>,
+    runtime: Option<Handle>,
+    revision: u32,
+    /// The addresses of the windows listed by the last `GetLayout`, in order.
+    served: Vec<String>,
+}
+
+/// Summary menu item ids, next to the window entries.
+const SUMMARY_RESTORE_ALL: i32 = 1;
+const SUMMARY_SEPARATOR: i32 = 2;
+/// The summary menu's window entries use this plus the window's index.
+const SUMMARY_WINDOW_BASE: i32 = 100;
+
+/// Serves the summary item and its menu at the given paths.
+pub async fn add_summary(
+    conn: &Connection,
+    paths: &ItemPaths,
+    summary: Arc<dyn Summary>,
+) -> Result<()> {
+    let menu = SummaryMenu {
+        summary: Arc::clone(&summary),
+        runtime: Handle::try_current().ok(),
+        revision: 1,
+        served: Vec::new(),
+    };
+    let item = SummaryItem {
+        summary,
+        menu_path: paths.menu.clone(),
+    };
+    let server = conn.object_server();
+    server
+        .at(paths.item.as_str(), FreedesktopSummaryItem(item.clone()))
+        .await?;
+    server.at(paths.item.as_str(), item).await?;
+    server.at(paths.menu.as_str(), menu).await?;
+    Ok(())
+}
+
+/// Stops serving the summary item added with [`add_summary`].
+pub async fn remove_summary(conn: &Connection, paths: &ItemPaths) -> Result<()> {
+    let server = conn.object_server();
+    server.remove::<SummaryItem, _>(paths.item.as_str()).await?;
+    server
+        .remove::<FreedesktopSummaryItem, _>(paths.item.as_str())
+        .await?;
+    server.remove::<SummaryMenu, _>(paths.menu.as_str()).await?;
+    Ok(())
+}
+
+/// Tells the hosts of the summary item that the managed windows changed.
+pub async fn summary_changed(conn: &Connection, paths: &ItemPaths) -> Result<()> {
+    let server = conn.object_server();
+    let item = server
+        .interface::<_, SummaryItem>(paths.item.as_str())
+        .await?;
+    let status = item.get().await.status();
+    let ctxt = item.signal_context();
+    SummaryItem::new_overlay_icon(ctxt).await?;
+    SummaryItem::new_tool_tip(ctxt).await?;
+    SummaryItem::new_status(ctxt, status).await?;
+    FreedesktopSummaryItem::new_overlay_icon(ctxt).await?;
+    FreedesktopSummaryItem::new_tool_tip(ctxt).await?;
+    FreedesktopSummaryItem::new_status(ctxt, status).await?;
+
+    let menu = server
+        .interface::<_, SummaryMenu>(paths.menu.as_str())
+        .await?;
+    let revision = {
+        let mut menu = menu.get_mut().await;
+        menu.revision += 1;
+        menu.revision
+    };
+    SummaryMenu::layout_updated(menu.signal_context(), revision, 0).await?;
+    Ok(())
+}
+
+impl SummaryMenu {
+    /// Returns the menu items with the addresses of the windows they restore.
+    fn items(&self) -> (Vec<MenuEntry>, Vec<String>) {
+        let mut windows = self.summary.windows();
+        windows.sort_by_key(display_title);
+        let mut items: Vec<MenuEntry> = windows
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                MenuEntry::new(
+                    SUMMARY_WINDOW_BASE + i as i32,
+                    standard_item(display_title(w)),
+                )
+            })
+            .collect();
+        items.push(MenuEntry::new(SUMMARY_SEPARATOR, separator_item()));
+        items.push(MenuEntry::new(
+            SUMMARY_RESTORE_ALL,
+            standard_item("Restore all and quit".to_string()),
+        ));
+        (items, windows.into_iter().map(|w| w.address).collect())
+    }
+}
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl SummaryMenu {
+    fn get_layout(
+        &mut self,
+        _parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> (u32, MenuLayout) {
+        let (items, addresses) = self.items();
+        self.served = addresses;
+        let children = items.into_iter().map(MenuEntry::into_layout).collect();
+        let mut root_props = HashMap::new();
+        root_props.insert("children-display".to_string(), Value::from("submenu"));
+        (self.revision, (0, root_props, children))
+    }
+
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, MenuProperties)> {
+        let mut all = Vec::new();
+        for item in self.items().0 {
+            item.flatten(&mut all);
+        }
+        all.retain(|(id, _)| ids.contains(id));
+        all
+    }
+
+    fn event_group(&self, events: Vec<(i32, String, Value<'_>, u32)>) {
+        for (id, event_id, data, timestamp) in events {
+            self.event(id, &event_id, data, timestamp);
+        }
+    }
+
+    fn event(&self, id: i32, event_id: &str, _data: Value<'_>, _timestamp: u32) {
+        if event_id != "clicked" {
+            return;
+        }
+        debug!("[Summary Menu] Clicked on item {}", id);
+        let summary = Arc::clone(&self.summary);
+        if id == SUMMARY_RESTORE_ALL {
+            info!("[Summary Menu] 'Restore all and quit' action triggered.");
+            run_blocking(&self.runtime, move || summary.restore_all_and_quit());
+            return;
+        }
+        let Some(address) = usize::try_from(id - SUMMARY_WINDOW_BASE)
+            .ok()
+            .and_then(|i| self.served.get(i))
+            .cloned()
+        else {
+            info!("[Summary Menu] Clicked on unknown item id: {}", id);
+            return;
+        };
+        run_blocking(&self.runtime, move || summary.restore(&address));
+    }
+
+    /// The layout is sent again whenever the windows change, so it is never stale.
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (vec![], vec![])
+    }
+
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    #[dbus_interface(signal)]
+    async fn layout_updated(
+        ctxt: &SignalContext<'_>,
+        revision: u32,
+        parent: i32,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+}
+
+impl SummaryItem {
+    /// Hidden while there is nothing minimized.
+    fn status(&self) -> &'static str {
+        if self.summary.windows().is_empty() {
+            ItemStatus::Passive.as_str()
+        } else {
+            ItemStatus::Active.as_str()
+        }
+    }
+}
+
+/// Implements the SNI interface `$name` on `$item`, like `status_notifier_item!`.
+macro_rules! summary_item {
+    ($name:tt, $item:ident) => {
+        #[dbus_interface(name = $name)]
+        impl $item {
+            #[dbus_interface(property)]
+            fn category(&self) -> &str {
+                "ApplicationStatus"
+            }
+
+            #[dbus_interface(property)]
+            fn id(&self) -> &str {
+                "hyprland-minimizer"
+            }
+
+            #[dbus_interface(property)]
+            fn title(&self) -> &str {
+                "Minimized windows"
+            }
+
+            #[dbus_interface(property, name = "Status")]
+            fn status_property(&self) -> &str {
+                self.status()
+            }
+
+            #[dbus_interface(property)]
+            fn icon_name(&self) -> &str {
+                SUMMARY_ICON
+            }
+
+            #[dbus_interface(property)]
+            fn icon_pixmap(&self) -> Vec<Pixmap> {
+                Vec::new()
+            }
+
+            /// Always empty: the badge is drawn, see `overlay_icon_pixmap`.
+            #[dbus_interface(property)]
+            fn overlay_icon_name(&self) -> &str {
+                ""
+            }
+
+            /// A badge with the number of minimized windows.
+            #[dbus_interface(property)]
+            fn overlay_icon_pixmap(&self) -> Vec<Pixmap> {
+                match self.summary.windows().len() {
+                    0 => Vec::new(),
+                    count => vec![icon::badge(count)],
+                }
+            }
+
+            #[dbus_interface(property)]
+            fn attention_icon_name(&self) -> &str {
+                SUMMARY_ICON
+            }
+
+            #[dbus_interface(property)]
+            fn tool_tip(&self) -> ToolTip {
+                let mut titles: Vec<String> =
+                    self.summary.windows().iter().map(display_title).collect();
+                titles.sort();
+                (
+                    SUMMARY_ICON.to_string(),
+                    Vec::new(),
+                    format!("{} minimized window(s)", titles.len()),
+                    titles.join("\n"),
+                )
+            }
+
+            /// Clicks open the menu, which lists the windows.
+            #[dbus_interface(property)]
+            fn item_is_menu(&self) -> bool {
+                true
+            }
+
+            #[dbus_interface(property)]
+            fn menu(&self) -> ObjectPath<'_> {
+                ObjectPath::try_from(self.menu_path.as_str()).unwrap()
+            }
+
+            #[dbus_interface(signal)]
+            async fn new_overlay_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn new_status(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+        }
+    };
+}
+
+summary_item!("org.kde.StatusNotifierItem", SummaryItem);
+summary_item!("org.freedesktop.StatusNotifierItem", FreedesktopSummaryItem);
+
+/// The theme icon of the summary item.
+const SUMMARY_ICON: &str = "window-minimize";
+
+// --- Developer Tools ---
+
+/// Calls every `DbusMenu` method directly against a sample window and prints the results.
+/// The menu still asks Hyprland for the workspaces; click events are dry runs
+/// (see `--dry-run`) that print what they would dispatch.
+pub async fn probe_dbusmenu(args: Arc<Args>, compositor: Arc<dyn Compositor>) {
+    let sample = WindowInfo {
+        address: "0x0".to_string(),
+        workspace: Workspace {
+            id: 1,
+            ..Workspace::default()
+        },
+        title: "Sample Window".to_string(),
+        class: "sample-class".to_string(),
+        ..WindowInfo::default()
+    };
+    let mut menu = DbusMenu::new(
+        sample,
+        args,
+        compositor,
+        Arc::new(Notify::new()),
+        Arc::new(Notify::new()),
+    );
+    // The clicks run in order, before the probe returns.
+    menu.runtime = None;
+
+    // Sorted `key=value` pairs, with values in the GVariant text format.
+    let describe = |props: &MenuProperties| {
+        let mut pairs: Vec<String> = props.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        pairs.sort();
+        pairs.join(" ")
+    };
+
+    let (revision, (root_id, root_props, children)) = menu.get_layout(0, -1, Vec::new()).await;
+    println!("== GetLayout (revision {}) ==", revision);
+    println!("root {}: {}", root_id, describe(&root_props));
+    for child in &children {
+        println!("  {}", child);
+    }
+
+    let ids: Vec<i32> = menu.all_items().into_iter().map(|(id, _)| id).collect();
+    println!("== GetGroupProperties {:?} ==", ids);
+    for (id, props) in menu.get_group_properties(ids.clone(), Vec::new()) {
+        println!("  {}: {}", id, describe(&props));
+    }
+
+    println!("== AboutToShowGroup {:?} ==", ids);
+    println!("  {:?}", menu.about_to_show_group(ids.clone()).await);
+
+    for id in ids {
+        println!("== Event {} 'clicked' ==", id);
+        menu.event(id, "clicked", Value::from(0i32), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::fake::FakeCompositor;
+    use clap::Parser;
+    use serde_json::json;
+
+    fn window(address: &str) -> WindowInfo {
+        WindowInfo {
+            address: address.to_string(),
+            workspace: Workspace {
+                id: 1,
+                name: "1".to_string(),
+            },
+            title: "Editor".to_string(),
+            class: "editor".to_string(),
+            ..WindowInfo::default()
+        }
+    }
+
+    fn menu(window_info: WindowInfo, args: &[&str], compositor: &Arc<FakeCompositor>) -> DbusMenu {
+        let args = std::iter::once("hyprland-minimizer").chain(args.iter().copied());
+        DbusMenu::new(
+            window_info,
+            Arc::new(Args::parse_from(args)),
+            Arc::clone(compositor) as Arc<dyn Compositor>,
+            Arc::new(Notify::new()),
+            Arc::new(Notify::new()),
+        )
+    }
+
+    fn item(window_info: WindowInfo, compositor: &Arc<FakeCompositor>) -> StatusNotifierItem {
+        let args = Args::parse_from(["hyprland-minimizer"]);
+        StatusNotifierItem {
+            window_info,
+            args: Arc::new(args),
+            compositor: Arc::clone(compositor) as Arc<dyn Compositor>,
+            quirks: TrayQuirks::default(),
+            learned_target: None,
+            auto_close_at: None,
+            status: Arc::new(Mutex::new(ItemStatus::Active)),
+            icon_pixmap: Vec::new(),
+            monitor_name: "DP-1".to_string(),
+            menu_path: "/MenuBar".to_string(),
+            exit_notify: Arc::new(Notify::new()),
+            close_request: Arc::new(Notify::new()),
+            activation_token: Arc::default(),
+            runtime: None,
+        }
+    }
+
+    #[test]
+    fn open_moves_to_the_active_workspace_and_focuses() {
+        let compositor = Arc::new(FakeCompositor::default());
+        compositor.reply("activeworkspace", json!({"id": 3, "name": "3"}));
+        let menu = menu(window("0xabc"), &[], &compositor);
+
+        menu.event(MENU_OPEN, "clicked", Value::from(0i32), 0);
+
+        assert_eq!(
+            compositor.dispatched(),
+            [
+                "movetoworkspace 3,address:0xabc",
+                "focuswindow address:0xabc"
+            ]
+        );
+    }
+
+    /// Returns the ids of the layout nodes, in order.
+    fn layout_ids(children: &[Value<'static>]) -> Vec<i32> {
+        children
+            .iter()
+            .map(|child| match child {
+                Value::Structure(node) => match node.fields()[0] {
+                    Value::I32(id) => id,
+                    ref other => panic!("node id {:?} is not an i32", other),
+                },
+                other => panic!("layout node {:?} is not a structure", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn compact_menu_has_restore_and_close_only() {
+        let compositor = Arc::new(FakeCompositor::default());
+        let mut menu = menu(window("0xabc"), &["--compact-menu"], &compositor);
+
+        let (_, (root, _, children)) = menu.get_layout(0, -1, Vec::new()).await;
+
+        assert_eq!(root, 0);
+        assert_eq!(
+            layout_ids(&children),
+            [MENU_OPEN, MENU_SEPARATOR, MENU_CLOSE]
+        );
+        let props: HashMap<i32, MenuProperties> = menu
+            .get_group_properties(vec![MENU_OPEN, MENU_CLOSE], Vec::new())
+            .into_iter()
+            .collect();
+        assert_eq!(props[&MENU_OPEN]["label"], Value::from("Restore Editor"));
+        assert_eq!(props[&MENU_CLOSE]["label"], Value::from("Close Editor"));
+        // Serving the layout dispatches nothing.
+        assert!(compositor.dispatched().is_empty());
+    }
+
+    #[tokio::test]
+    async fn workspace_submenu_lists_the_workspaces_fetched_for_the_layout() {
+        let compositor = Arc::new(FakeCompositor::default());
+        compositor.reply(
+            "workspaces",
+            serde_json::json!([
+                { "id": 2, "name": "web", "windows": 1 },
+                { "id": -99, "name": "special:minimized", "windows": 1 },
+                { "id": 1, "name": "1", "windows": 0 }
+            ]),
+        );
+        let mut menu = menu(window("0xabc"), &[], &compositor);
+
+        menu.get_layout(0, -1, Vec::new()).await;
+        // Later lookups use the fetched list, not a new query.
+        compositor.reply("workspaces", serde_json::json!([]));
+
+        let moves = vec![MENU_WORKSPACE_BASE + 1, MENU_WORKSPACE_BASE + 2];
+        let labels: Vec<_> = menu
+            .get_group_properties(moves, Vec::new())
+            .into_iter()
+            .map(|(_, props)| props["label"].clone())
+            .collect();
+        assert_eq!(labels, [Value::from("1"), Value::from("web")]);
+    }
+
+    #[tokio::test]
+    async fn restore_to_the_original_workspace_is_greyed_out_while_it_is_active() {
+        let compositor = Arc::new(FakeCompositor::default());
+        compositor.reply("activeworkspace", json!({"id": 1, "name": "1"}));
+        let mut menu = menu(window("0xabc"), &[], &compositor);
+
+        let props = |menu: &DbusMenu| {
+            menu.get_group_properties(vec![MENU_OPEN_ORIGINAL], Vec::new())
+                .remove(0)
+                .1
+        };
+
+        menu.get_layout(0, -1, Vec::new()).await;
+        assert_eq!(props(&menu)["enabled"], Value::from(false));
+
+        compositor.reply("activeworkspace", json!({"id": 3, "name": "3"}));
+        // The switch shows once the host asks for the menu again.
+        assert_eq!(props(&menu)["enabled"], Value::from(false));
+        assert!(menu.about_to_show(0).await);
+        assert_eq!(props(&menu)["enabled"], Value::from(true));
+    }
+
+    #[test]
+    fn open_on_original_moves_to_the_original_workspace() {
+        let compositor = Arc::new(FakeCompositor::default());
+        let menu = menu(window("0xabc"), &[], &compositor);
+
+        menu.event(MENU_OPEN_ORIGINAL, "clicked", Value::from(0i32), 0);
+
+        assert_eq!(
+            compositor.dispatched(),
+            [
+                "movetoworkspace 1,address:0xabc",
+                "focuswindow address:0xabc"
+            ]
+        );
+    }
+
+    /// Lists fixed windows and records which ones get restored.
+    #[derive(Default)]
+    struct FakeSummary {
+        windows: Vec<WindowInfo>,
+        restored: Mutex<Vec<String>>,
+        quit: std::sync::atomic::AtomicBool,
+    }
+
+    impl Summary for FakeSummary {
+        fn windows(&self) -> Vec<WindowInfo> {
+            self.windows.clone()
+        }
+
+        fn restore(&self, address: &str) {
+            self.restored.lock().unwrap().push(address.to_string());
+        }
+
+        fn restore_all_and_quit(&self) {
+            self.quit.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    fn summary_menu(summary: &Arc<FakeSummary>) -> SummaryMenu {
+        SummaryMenu {
+            summary: Arc::clone(summary) as Arc<dyn Summary>,
+            runtime: None,
+            revision: 1,
+            served: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn summary_menu_restores_the_clicked_window() {
+        let summary = Arc::new(FakeSummary {
+            windows: vec![
+                WindowInfo {
+                    title: "Terminal".to_string(),
+                    ..window("0x1")
+                },
+                WindowInfo {
+                    title: "Browser".to_string(),
+                    ..window("0x2")
+                },
+            ],
+            ..FakeSummary::default()
+        });
+        let mut menu = summary_menu(&summary);
+
+        let (_, (_, _, children)) = menu.get_layout(0, -1, Vec::new());
+        assert_eq!(children.len(), 4);
+        let labels: Vec<String> = menu
+            .get_group_properties(
+                vec![SUMMARY_WINDOW_BASE, SUMMARY_WINDOW_BASE + 1],
+                Vec::new(),
+            )
+            .into_iter()
+            .map(|(_, props)| props["label"].to_string())
+            .collect();
+        assert_eq!(labels, ["\"Browser\"", "\"Terminal\""]);
+
+        menu.event(SUMMARY_WINDOW_BASE + 1, "clicked", Value::from(0i32), 0);
+        assert_eq!(*summary.restored.lock().unwrap(), ["0x1"]);
+    }
+
+    #[test]
+    fn summary_menu_restores_all_and_quits() {
+        let summary = Arc::new(FakeSummary {
+            windows: vec![window("0x1")],
+            ..FakeSummary::default()
+        });
+        let mut menu = summary_menu(&summary);
+        menu.get_layout(0, -1, Vec::new());
+
+        menu.event(SUMMARY_RESTORE_ALL, "clicked", Value::from(0i32), 0);
+
+        assert!(summary.quit.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(summary.restored.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn custom_icon_is_also_the_attention_icon() {
+        let compositor = Arc::new(FakeCompositor::default());
+        let mut item = item(window("0xabc"), &compositor);
+        let mut args = Args::parse_from(["hyprland-minimizer"]);
+        args.icon = Some("icon.png".into());
+        item.args = Arc::new(args);
+        item.icon_pixmap = vec![(1, 1, vec![255, 0, 0, 0])];
+
+        assert_eq!(item.attention_icon_name(), "");
+        assert_eq!(item.attention_icon_pixmap(), item.icon_pixmap);
+    }
+
+    #[test]
+    fn tooltip_shows_the_monitor_looked_up_with_the_item() {
+        // No `monitors` reply: the tooltip must not need one.
+        let compositor = Arc::new(FakeCompositor::default());
+        let item = item(window("0xabc"), &compositor);
+
+        let (_, _, title, description) = item.tool_tip();
+
+        assert_eq!(title, "Editor");
+        assert!(description.contains("Monitor: DP-1"));
+        assert_eq!(monitor_name(&*compositor, 2), "2");
+    }
+
+    #[test]
+    fn items_only_ever_dispatch_against_their_own_window() {
+        let compositor = Arc::new(FakeCompositor::default());
+        compositor.reply("activeworkspace", json!({"id": 3, "name": "3"}));
+        let windows = [window("0xaaa"), window("0xbbb")];
+
+        for window_info in windows {
+            let address = format!("address:{}", window_info.address);
+            let menu = menu(window_info.clone(), &[], &compositor);
+            let item = item(window_info, &compositor);
+            let before = compositor.dispatched().len();
+
+            for id in [
+                MENU_OPEN,
+                MENU_OPEN_ORIGINAL,
+                MENU_PIN,
+                MENU_WORKSPACE_BASE + 2,
+                MENU_CLOSE,
+            ] {
+                menu.event(id, "clicked", Value::from(0i32), 0);
+            }
+            for action in [
+                ClickAction::Restore,
+                ClickAction::RestoreCurrent,
+                ClickAction::RestoreOriginal,
+                ClickAction::Close,
+            ] {
+                item.run_click_action(action, None);
+            }
+            item.scroll(1, "vertical");
+
+            let dispatched = &compositor.dispatched()[before..];
+            assert!(!dispatched.is_empty());
+            for command in dispatched {
+                assert!(
+                    !command.contains("address:") || command.ends_with(&address),
+                    "'{}' is not scoped to {}",
+                    command,
+                    address
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Reader for Hyprland's event socket (`.socket2.sock`).
+//! Hyprland writes one `EVENT>>DATA` line per event to every connected client.
+//! On Sway, the same lines are made up from its window events, see `sway::Events`,
+//! and with `--backend wlr` from changes to the toplevels, see `wlr::Events`.
+
+use crate::ipc::{self, Backend};
+use crate::{sway, wlr};
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::net::UnixStream;
+
+/// A single event received from the event socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub name: String,
+    pub data: String,
+}
+
+impl Event {
+    /// Parses a raw `EVENT>>DATA` line.
+    pub fn parse(line: &str) -> Option<Event> {
+        let (name, data) = line.split_once(">>")?;
+        Some(Event {
+            name: name.to_string(),
+            data: data.to_string(),
+        })
+    }
+
+    /// Returns the window address this event refers to, if its first field is one.
+    /// Event addresses are sent without the `0x` prefix that `hyprctl -j` uses,
+    /// so the prefix is added back here.
+    pub fn address(&self) -> Option<String> {
+        let raw = self.data.split(',').next()?;
+        if raw.is_empty() || !raw.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(format!("0x{}", raw))
+    }
+}
+
+/// Returns the path of the event socket for the current Hyprland instance.
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(crate::ipc::instance_dir()?.join(".socket2.sock"))
+}
+
+/// How long the Sway event stream waits for the tree it looks up moved windows in.
+const SWAY_TREE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the wlr event stream waits for the compositor when it makes the connection.
+const WLR_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the events come from.
+enum Source {
+    Hyprland(Lines<BufReader<UnixStream>>),
+    /// Sway's events, which can stand for several Hyprland events each.
+    Sway(sway::Events, VecDeque<String>),
+    Wlr(wlr::Events),
+}
+
+/// A connected event stream.
+pub struct EventStream {
+    source: Source,
+}
+
+impl EventStream {
+    /// Connects to the event socket of the current Hyprland instance, or to
+    /// the other backends' events.
+    pub async fn connect() -> Result<EventStream> {
+        match ipc::backend() {
+            Backend::Hyprland => {}
+            Backend::Sway => {
+                let events = sway::Events::subscribe(SWAY_TREE_TIMEOUT).await?;
+                return Ok(EventStream {
+                    source: Source::Sway(events, VecDeque::new()),
+                });
+            }
+            Backend::Wlr => {
+                let events = wlr::Events::subscribe(WLR_CONNECT_TIMEOUT).await?;
+                return Ok(EventStream {
+                    source: Source::Wlr(events),
+                });
+            }
+        }
+        let path = socket_path()?;
+        let stream = UnixStream::connect(&path)
+            .await
+            .with_context(|| format!("Failed to connect to event socket {}", path.display()))?;
+        Ok(EventStream {
+            source: Source::Hyprland(BufReader::new(stream).lines()),
+        })
+    }
+
+    /// Waits for the next raw event line. Returns `None` when the socket is closed.
+    async fn next_line(&mut self) -> Result<Option<String>> {
+        match &mut self.source {
+            Source::Hyprland(lines) => Ok(lines.next_line().await?),
+            Source::Sway(events, pending) => {
+                while pending.is_empty() {
+                    match events.next().await? {
+                        Some(lines) => pending.extend(lines),
+                        None => return Ok(None),
+                    }
+                }
+                Ok(pending.pop_front())
+            }
+            Source::Wlr(events) => Ok(events.next().await),
+        }
+    }
+
+    /// Waits for the next event. Returns `None` when the socket is closed.
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        loop {
+            match self.next_line().await? {
+                Some(line) => {
+                    if let Some(event) = Event::parse(&line) {
+                        return Ok(Some(event));
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,648 @@
+//! Finding and decoding window icons for the SNI `IconPixmap` property.
+//! Icons are looked up through the window's `.desktop` file and the hicolor
+//! theme. Only PNG is understood; the decoder below handles the non-interlaced
+//! 8-bit images icon themes ship, which keeps image crates out of the build.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// One SNI pixmap: width, height and ARGB32 pixels in network byte order.
+pub type Pixmap = (i32, i32, Vec<u8>);
+
+/// Icon sizes tried in the theme, best fit for a tray first.
+const THEME_SIZES: [&str; 8] = ["48", "64", "32", "128", "256", "24", "22", "16"];
+
+/// Returns the XDG data directories, most specific first.
+fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => dirs.push(PathBuf::from(dir)),
+        None => {
+            if let Some(home) = std::env::var_os("HOME") {
+                dirs.push(PathBuf::from(home).join(".local").join("share"));
+            }
+        }
+    }
+    let system = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs.extend(system.split(':').map(PathBuf::from));
+    dirs
+}
+
+/// Returns the `Icon=` value of the desktop entry, if it has one.
+fn desktop_icon(entry: &str) -> Option<String> {
+    entry
+        .lines()
+        .find_map(|line| line.strip_prefix("Icon="))
+        .map(|icon| icon.trim().to_string())
+        .filter(|icon| !icon.is_empty())
+}
+
+/// Returns the application name from a `.desktop` file.
+fn desktop_name(entry: &str) -> Option<String> {
+    entry
+        .lines()
+        .find_map(|line| line.strip_prefix("Name="))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Finds the icon named by the `.desktop` file of a window class.
+fn icon_name_for_class(class: &str) -> Option<String> {
+    desktop_icon(&desktop_entry_for_class(class)?)
+}
+
+/// Returns the application name from the `.desktop` file of a window class.
+pub fn app_name_for_class(class: &str) -> Option<String> {
+    desktop_name(&desktop_entry_for_class(class)?)
+}
+
+/// Reads the `.desktop` file of a window class: either `<class>.desktop` or an
+/// entry whose `StartupWMClass` is the class.
+fn desktop_entry_for_class(class: &str) -> Option<String> {
+    let candidates = [
+        format!("{}.desktop", class),
+        format!("{}.desktop", class.to_lowercase()),
+    ];
+    let app_dirs: Vec<PathBuf> = data_dirs()
+        .into_iter()
+        .map(|d| d.join("applications"))
+        .collect();
+    for dir in &app_dirs {
+        for name in &candidates {
+            if let Ok(entry) = std::fs::read_to_string(dir.join(name)) {
+                return Some(entry);
+            }
+        }
+    }
+    let wm_class = format!("StartupWMClass={}", class);
+    for dir in &app_dirs {
+        let Ok(files) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for file in files.flatten() {
+            if let Ok(entry) = std::fs::read_to_string(file.path()) {
+                if entry.lines().any(|line| line.trim() == wm_class) {
+                    return Some(entry);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Finds a PNG for an icon name or path in the hicolor theme or the pixmaps directory.
+fn find_icon_file(icon: &str) -> Option<PathBuf> {
+    if icon.starts_with('/') {
+        return Some(PathBuf::from(icon));
+    }
+    let file_name = format!("{}.png", icon);
+    for dir in data_dirs() {
+        for size in THEME_SIZES {
+            let path = dir
+                .join("icons/hicolor")
+                .join(format!("{}x{}", size, size))
+                .join("apps")
+                .join(&file_name);
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+        let path = dir.join("pixmaps").join(&file_name);
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Looks up and decodes the icon of a window class.
+/// Returns `None` when the class has no icon we can find.
+pub fn for_class(class: &str) -> Result<Option<Pixmap>> {
+    let icon = icon_name_for_class(class).unwrap_or_else(|| class.to_string());
+    match find_icon_file(&icon) {
+        Some(path) => load(&path).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads and decodes a PNG file.
+pub fn load(path: &Path) -> Result<Pixmap> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+    {
+        bail!("{} is an SVG; only PNG icons are supported", path.display());
+    }
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read icon {}", path.display()))?;
+    decode_png(&bytes).with_context(|| format!("Failed to decode icon {}", path.display()))
+}
+
+// --- Count Badges ---
+
+/// Edge length of a badge pixmap.
+const BADGE_SIZE: usize = 24;
+
+/// 3x5 glyphs for the digits and `+`, one row per byte, the lowest three bits used.
+const BADGE_GLYPHS: [[u8; 5]; 11] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b000, 0b010, 0b111, 0b010, 0b000],
+];
+
+/// Draws a red disc with the count in white, for the SNI `OverlayIconPixmap`.
+/// Counts above 9 show as `9+`, which is all that fits.
+pub fn badge(count: usize) -> Pixmap {
+    let glyphs: Vec<usize> = match count {
+        0..=9 => vec![count],
+        _ => vec![9, 10],
+    };
+    let scale = if glyphs.len() == 1 { 3 } else { 2 };
+    let text_width = (glyphs.len() * 4 - 1) * scale;
+    let text_height = 5 * scale;
+    let left = (BADGE_SIZE - text_width) / 2;
+    let top = (BADGE_SIZE - text_height) / 2;
+    let lit = |x: usize, y: usize| {
+        if x < left || y < top || x >= left + text_width || y >= top + text_height {
+            return false;
+        }
+        let (column, row) = ((x - left) / scale, (y - top) / scale);
+        let (glyph, column) = (column / 4, column % 4);
+        column < 3 && BADGE_GLYPHS[glyphs[glyph]][row] & (0b100 >> column) != 0
+    };
+
+    let radius = BADGE_SIZE as f32 / 2.0;
+    let mut argb = Vec::with_capacity(BADGE_SIZE * BADGE_SIZE * 4);
+    for y in 0..BADGE_SIZE {
+        for x in 0..BADGE_SIZE {
+            let (dx, dy) = (x as f32 + 0.5 - radius, y as f32 + 0.5 - radius);
+            let pixel = if dx * dx + dy * dy > radius * radius {
+                [0, 0, 0, 0]
+            } else if lit(x, y) {
+                [255, 255, 255, 255]
+            } else {
+                [255, 220, 40, 40]
+            };
+            argb.extend_from_slice(&pixel);
+        }
+    }
+    (BADGE_SIZE as i32, BADGE_SIZE as i32, argb)
+}
+
+// --- PNG Decoding ---
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// The largest width and height decoded. Theme icons top out at 1024 pixels; the
+/// cap keeps a corrupt or hostile header from allocating gigabytes.
+const MAX_DIMENSION: usize = 4096;
+
+/// Decodes an 8-bit, non-interlaced PNG into ARGB32 pixels.
+fn decode_png(bytes: &[u8]) -> Result<Pixmap> {
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        bail!("not a PNG file");
+    }
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut rest = &bytes[PNG_SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let data = rest
+            .get(8..8 + length)
+            .ok_or_else(|| anyhow!("truncated chunk"))?;
+        match kind {
+            b"IHDR" if data.len() >= 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = rest.get(12 + length..).unwrap_or(&[]);
+    }
+
+    let header = header.ok_or_else(|| anyhow!("missing IHDR chunk"))?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let (bit_depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if bit_depth != 8 || interlace != 0 {
+        bail!("only 8-bit non-interlaced PNGs are supported");
+    }
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        bail!(
+            "unsupported image size {}x{} (at most {} pixels per side)",
+            width,
+            height,
+            MAX_DIMENSION
+        );
+    }
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => bail!("unknown color type {}", color_type),
+    };
+
+    // Each line starts with its filter type.
+    let stride = width
+        .checked_mul(channels)
+        .ok_or_else(|| anyhow!("image is too large"))?;
+    let expected = (stride + 1)
+        .checked_mul(height)
+        .ok_or_else(|| anyhow!("image is too large"))?;
+    let raw = zlib_decompress(&compressed, expected)?;
+    if raw.len() < expected {
+        bail!("image data is too short");
+    }
+    let mut pixels = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, current) = pixels.split_at_mut(y * stride);
+        let previous = if y > 0 {
+            &done[(y - 1) * stride..]
+        } else {
+            &[][..]
+        };
+        let current = &mut current[..stride];
+        for x in 0..stride {
+            let a = if x >= channels {
+                current[x - channels]
+            } else {
+                0
+            };
+            let b = previous.get(x).copied().unwrap_or(0);
+            let c = if x >= channels {
+                previous.get(x - channels).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => bail!("unknown filter type {}", filter),
+            };
+            current[x] = line[x].wrapping_add(predicted);
+        }
+    }
+
+    let mut argb = Vec::with_capacity(width * height * 4);
+    for pixel in pixels.chunks_exact(channels) {
+        let (r, g, b, a) = match color_type {
+            0 => (pixel[0], pixel[0], pixel[0], 255),
+            2 => (pixel[0], pixel[1], pixel[2], 255),
+            3 => {
+                let index = pixel[0] as usize;
+                let rgb = palette
+                    .get(index * 3..index * 3 + 3)
+                    .ok_or_else(|| anyhow!("palette index {} out of range", index))?;
+                let alpha = transparency.get(index).copied().unwrap_or(255);
+                (rgb[0], rgb[1], rgb[2], alpha)
+            }
+            4 => (pixel[0], pixel[0], pixel[0], pixel[1]),
+            _ => (pixel[0], pixel[1], pixel[2], pixel[3]),
+        };
+        argb.extend_from_slice(&[a, r, g, b]);
+    }
+    Ok((width as i32, height as i32, argb))
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// --- Inflate (RFC 1950/1951) ---
+
+/// Reads bits least significant first, as deflate packs them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| anyhow!("compressed data ends early"))?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left in the current byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16> {
+        // Canonical codes of one length are consecutive, see RFC 1951 3.2.2.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[len] as i32;
+            if code - count < first {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("invalid Huffman code")
+    }
+}
+
+/// A canonical Huffman code: how many codes each length has and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for len in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, &l)| l == len) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman { counts, symbols }
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are stored in for dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a zlib stream of at most `limit` bytes. The Adler-32 trailer is
+/// not checked.
+fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 || data[1] & 0x20 != 0 {
+        bail!("unsupported zlib stream");
+    }
+    let mut reader = BitReader {
+        data: &data[2..],
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader
+                    .data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or_else(|| anyhow!("compressed data ends early"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let stored = reader
+                    .data
+                    .get(reader.pos + 4..reader.pos + 4 + len)
+                    .ok_or_else(|| anyhow!("compressed data ends early"))?;
+                out.extend_from_slice(stored);
+                reader.pos += 4 + len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &literals, &distances, &mut out, limit)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut out, limit)?;
+            }
+            _ => bail!("invalid block type"),
+        }
+        if out.len() > limit {
+            bail!("compressed data inflates beyond {} bytes", limit);
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match reader.decode(&code_length_code)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| anyhow!("repeat without a previous length"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        bail!("code lengths overflow");
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+    limit: usize,
+) -> Result<()> {
+    while out.len() <= limit {
+        let symbol = reader.decode(literals)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    bail!("invalid length symbol");
+                }
+                let length =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = reader.decode(distances)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    bail!("invalid distance symbol");
+                }
+                let distance = DISTANCE_BASE[index] as usize
+                    + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    bail!("distance reaches before the start of the data");
+                }
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+    bail!("compressed data inflates beyond {} bytes", limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a PNG from its header fields and zlib-compressed image data. The
+    /// CRCs are zero, the decoder doesn't check them.
+    fn png(width: u32, height: u32, color_type: u8, idat: &[u8]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        for (kind, data) in [(b"IHDR", &header[..]), (b"IDAT", idat), (b"IEND", &[])] {
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(kind);
+            bytes.extend_from_slice(data);
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn inflates_stored_blocks() {
+        let data = [
+            0x78, 0x01, 0x01, 0x0d, 0x00, 0xf2, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20,
+            0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x22, 0x4a, 0x04, 0xf2,
+        ];
+        assert_eq!(zlib_decompress(&data, 100).unwrap(), b"hello, stored");
+    }
+
+    #[test]
+    fn inflates_fixed_huffman_blocks() {
+        // Repeats "abc" through a back-reference.
+        let data = [
+            0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x4e, 0x44, 0x42, 0x0a, 0x69, 0x99, 0x15, 0xa9, 0x29,
+            0x00, 0x57, 0x67, 0x07, 0xef,
+        ];
+        assert_eq!(
+            zlib_decompress(&data, 100).unwrap(),
+            b"abcabcabcabcabc fixed"
+        );
+    }
+
+    #[test]
+    fn inflates_dynamic_huffman_blocks() {
+        let data = [
+            0x78, 0xda, 0x25, 0xc9, 0xb1, 0x0d, 0x00, 0x00, 0x08, 0xc3, 0xb0, 0x5b, 0x93, 0xf6,
+            0xff, 0x1b, 0x00, 0x31, 0x5a, 0x06, 0x0d, 0x48, 0xd5, 0x62, 0x2a, 0xcb, 0x12, 0xb9,
+            0xe0, 0x97, 0x01, 0x39, 0xf1, 0x0f, 0x4c,
+        ];
+        assert_eq!(
+            zlib_decompress(&data, 100).unwrap(),
+            b"aabbcaabadbbbdabcdbaaabdacbabcaaabcaabaa"
+        );
+    }
+
+    #[test]
+    fn stops_inflating_at_the_limit() {
+        let data = [
+            0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x4e, 0x44, 0x42, 0x0a, 0x69, 0x99, 0x15, 0xa9, 0x29,
+            0x00, 0x57, 0x67, 0x07, 0xef,
+        ];
+        assert!(zlib_decompress(&data, 10).is_err());
+    }
+
+    #[test]
+    fn decodes_filtered_rgba_lines() {
+        // Two lines of two pixels, the first with the Sub filter, the second with Up.
+        let idat = [
+            0x78, 0xda, 0x63, 0xe4, 0x12, 0x91, 0xfb, 0xcf, 0xca, 0xca, 0xca, 0xc0, 0xc4, 0xc8,
+            0xc8, 0xc8, 0xc0, 0xc4, 0xc4, 0xd4, 0x00, 0x00, 0x13, 0x2f, 0x01, 0xd7,
+        ];
+        let (width, height, argb) = decode_png(&png(2, 2, 6, &idat)).unwrap();
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(
+            argb,
+            [255, 10, 20, 30, 255, 15, 25, 35, 255, 11, 21, 31, 127, 17, 27, 37]
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_images_before_allocating() {
+        let error = decode_png(&png(u32::MAX, u32::MAX, 6, &[])).unwrap_err();
+        assert!(error.to_string().contains("unsupported image size"));
+    }
+}
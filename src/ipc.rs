@@ -0,0 +1,454 @@
+//! Requests to Hyprland. They go straight to its request socket (`.socket.sock`)
+//! when it is reachable, which saves spawning a `hyprctl` process per call;
+//! running `hyprctl` remains the fallback.
+//!
+//! Everything talking to the compositor takes a [`Compositor`], so tests can
+//! hand in a fake one, and other compositors can be served through it: see
+//! `--backend` and the `sway` and `wlr` modules.
+
+use crate::metrics;
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The compositor the minimizer talks to, in terms of Hyprland's IPC: queries
+/// such as `clients` answered with Hyprland's JSON, and dispatches.
+pub trait Compositor: Send + Sync {
+    /// Sends a query such as `clients` and returns its raw JSON reply.
+    fn query(&self, command: &str) -> Result<Vec<u8>>;
+
+    /// Sends a dispatch and returns Hyprland's reply, `ok` if it was accepted.
+    fn dispatch(&self, command: &str) -> Result<String>;
+
+    /// Sends several dispatches in one request and returns Hyprland's replies
+    /// run together, e.g. `okok` if both of two dispatches were accepted.
+    fn dispatch_batch(&self, commands: &[&str]) -> Result<String>;
+}
+
+/// Joins dispatches into the argument of a batch request.
+fn batch(commands: &[&str]) -> String {
+    commands
+        .iter()
+        .map(|c| format!("dispatch {}", c))
+        .collect::<Vec<_>>()
+        .join(" ; ")
+}
+
+impl dyn Compositor + '_ {
+    /// Sends a query and parses its reply.
+    pub fn query_json<T: DeserializeOwned>(&self, command: &str) -> Result<T> {
+        let reply = self.query(command)?;
+        serde_json::from_slice(&reply)
+            .with_context(|| format!("Failed to parse JSON from hyprctl command: {}", command))
+    }
+}
+
+/// The compositors the minimizer can talk to, picked with `--backend`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Hyprland,
+    /// Sway over its i3-compatible IPC, with the scratchpad as the special workspace.
+    Sway,
+    /// wlroots-based compositors over wlr-foreign-toplevel-management, with
+    /// minimized windows as the special workspace.
+    Wlr,
+}
+
+impl Backend {
+    /// The value `--backend` takes for this backend.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Hyprland => "hyprland",
+            Backend::Sway => "sway",
+            Backend::Wlr => "wlr",
+        }
+    }
+}
+
+/// The backend picked with `--backend`.
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Returns the backend the process talks to, Hyprland unless `connect` was told otherwise.
+pub fn backend() -> Backend {
+    BACKEND.get().copied().unwrap_or(Backend::Hyprland)
+}
+
+/// The instance picked with `--instance`, if any.
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+/// Returns the signature of the Hyprland instance all requests go to.
+pub fn signature() -> Result<String> {
+    if let Some(instance) = INSTANCE.get() {
+        return Ok(instance.clone());
+    }
+    std::env::var("HYPRLAND_INSTANCE_SIGNATURE").map_err(|_| {
+        anyhow!("HYPRLAND_INSTANCE_SIGNATURE is not set — are you running inside a Hyprland session? Pass --instance to pick one.")
+    })
+}
+
+/// Returns the runtime directory of the current Hyprland instance.
+pub fn instance_dir() -> Result<PathBuf> {
+    let signature = signature()?;
+
+    // Newer Hyprland versions keep their sockets in $XDG_RUNTIME_DIR, older ones in /tmp.
+    if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        let dir = PathBuf::from(runtime_dir).join("hypr").join(&signature);
+        if dir.exists() {
+            return Ok(dir);
+        }
+    }
+    Ok(PathBuf::from("/tmp/hypr").join(&signature))
+}
+
+/// Marks a socket request that failed before Hyprland got all of it, so sending
+/// it again through hyprctl cannot run it twice.
+#[derive(Debug)]
+struct NotSent;
+
+impl std::fmt::Display for NotSent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Hyprland's socket did not take the request")
+    }
+}
+
+/// Talks to Hyprland's request socket, one connection per request.
+pub struct SocketBackend {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl SocketBackend {
+    /// Returns the backend for the current instance, if its socket exists.
+    pub fn detect(timeout: Duration) -> Option<SocketBackend> {
+        let path = instance_dir().ok()?.join(".socket.sock");
+        path.exists().then_some(SocketBackend { path, timeout })
+    }
+
+    /// Sends a request and returns the reply. Errors before the request was
+    /// written in full carry [`NotSent`].
+    fn request(&self, request: &str) -> Result<Vec<u8>> {
+        let send = || -> Result<UnixStream> {
+            let mut stream = UnixStream::connect(&self.path)
+                .with_context(|| format!("Failed to connect to {}", self.path.display()))?;
+            // A hung compositor would otherwise block the caller forever.
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream
+                .write_all(request.as_bytes())
+                .with_context(|| format!("Failed to send '{}' to Hyprland", request))?;
+            Ok(stream)
+        };
+        let mut stream = send().context(NotSent)?;
+        // Hyprland closes the connection after its reply.
+        let mut reply = Vec::new();
+        stream
+            .read_to_end(&mut reply)
+            .with_context(|| format!("Failed to read Hyprland's reply to '{}'", request))?;
+        Ok(reply)
+    }
+}
+
+impl Compositor for SocketBackend {
+    fn query(&self, command: &str) -> Result<Vec<u8>> {
+        self.request(&format!("j/{}", command))
+    }
+
+    fn dispatch(&self, command: &str) -> Result<String> {
+        let reply = self.request(&format!("dispatch {}", command))?;
+        Ok(String::from_utf8_lossy(&reply).trim().to_string())
+    }
+
+    fn dispatch_batch(&self, commands: &[&str]) -> Result<String> {
+        let reply = self.request(&format!("[[BATCH]]{}", batch(commands)))?;
+        Ok(String::from_utf8_lossy(&reply).trim().to_string())
+    }
+}
+
+/// Runs `hyprctl` for every request.
+pub struct ProcessBackend {
+    timeout: Duration,
+}
+
+/// How often to check whether a hyprctl process has exited.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+impl ProcessBackend {
+    /// Runs hyprctl with `args`, explaining the usual cause when it cannot be
+    /// started and killing it if it does not finish within the timeout.
+    fn run(&self, args: &[&str], what: &str) -> Result<Output> {
+        // Spelling out the instance keeps hyprctl from guessing when several are running.
+        let instance = signature()?;
+        metrics::SUBPROCESS_SPAWNS_TOTAL.inc();
+        let mut child = match Command::new("hyprctl")
+            .arg("--instance")
+            .arg(&instance)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(anyhow!(
+                    "hyprctl not found on PATH — is Hyprland installed and are you running inside a Hyprland session?"
+                ))
+            }
+            Err(e) => return Err(e).with_context(|| what.to_string()),
+        };
+
+        // Read the output while waiting, so a large reply can't fill the pipe and stall hyprctl.
+        let mut stdout = child.stdout.take().context("hyprctl has no stdout")?;
+        let mut stderr = child.stderr.take().context("hyprctl has no stderr")?;
+        let stdout = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            stdout.read_to_end(&mut buf).map(|_| buf)
+        });
+        let stderr = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait().with_context(|| what.to_string())? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                anyhow::bail!(
+                    "{}: hyprctl did not finish within {}ms",
+                    what,
+                    self.timeout.as_millis()
+                );
+            }
+            std::thread::sleep(PROCESS_POLL_INTERVAL);
+        };
+        let read = |reader: std::thread::JoinHandle<std::io::Result<Vec<u8>>>| {
+            reader
+                .join()
+                .map_err(|_| anyhow!("{}: reading hyprctl's output failed", what))?
+                .with_context(|| what.to_string())
+        };
+        Ok(Output {
+            status,
+            stdout: read(stdout)?,
+            stderr: read(stderr)?,
+        })
+    }
+}
+
+impl Compositor for ProcessBackend {
+    fn query(&self, command: &str) -> Result<Vec<u8>> {
+        let output = self.run(
+            &["-j", command],
+            &format!("Failed to execute hyprctl command: {}", command),
+        )?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("hyprctl command '{}' failed: {}", command, stderr);
+        }
+        Ok(output.stdout)
+    }
+
+    fn dispatch(&self, command: &str) -> Result<String> {
+        let output = self.run(
+            &["dispatch", command],
+            &format!("Failed to execute hyprctl dispatch: {}", command),
+        )?;
+        let reply = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() && (reply.is_empty() || reply == "ok") {
+            return Ok(format!("hyprctl exited with {}", output.status));
+        }
+        Ok(reply)
+    }
+
+    fn dispatch_batch(&self, commands: &[&str]) -> Result<String> {
+        let batch = batch(commands);
+        let output = self.run(
+            &["--batch", &batch],
+            &format!("Failed to execute hyprctl batch: {}", batch),
+        )?;
+        let reply = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() && reply.is_empty() {
+            return Ok(format!("hyprctl exited with {}", output.status));
+        }
+        Ok(reply)
+    }
+}
+
+/// Prefers the request socket, falling back to `hyprctl` for each request the
+/// socket cannot serve. Dispatches only fall back if the socket did not take
+/// them: one that timed out may have run already, and toggles such as `pin`
+/// would be undone by running twice.
+struct AutoBackend {
+    socket: Option<SocketBackend>,
+    process: ProcessBackend,
+}
+
+impl Compositor for AutoBackend {
+    fn query(&self, command: &str) -> Result<Vec<u8>> {
+        if let Some(socket) = &self.socket {
+            match socket.query(command) {
+                Ok(reply) => return Ok(reply),
+                Err(e) => log::debug!("[IPC] {:#}, falling back to hyprctl.", e),
+            }
+        }
+        self.process.query(command)
+    }
+
+    fn dispatch(&self, command: &str) -> Result<String> {
+        if let Some(socket) = &self.socket {
+            match socket.dispatch(command) {
+                Ok(reply) => return Ok(reply),
+                Err(e) if e.is::<NotSent>() => {
+                    log::debug!("[IPC] {:#}, falling back to hyprctl.", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.process.dispatch(command)
+    }
+
+    fn dispatch_batch(&self, commands: &[&str]) -> Result<String> {
+        if let Some(socket) = &self.socket {
+            match socket.dispatch_batch(commands) {
+                Ok(reply) => return Ok(reply),
+                Err(e) if e.is::<NotSent>() => {
+                    log::debug!("[IPC] {:#}, falling back to hyprctl.", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.process.dispatch_batch(commands)
+    }
+}
+
+/// Returns the compositor of `backend`: for Hyprland, the instance picked with
+/// `instance`, or else the one in the environment. `timeout` is the time each
+/// request may take. Backend and instance apply to the whole process, e.g. to
+/// the event socket as well; only the first ones given count.
+pub fn connect(
+    backend: Backend,
+    timeout: Duration,
+    instance: Option<String>,
+) -> Arc<dyn Compositor> {
+    let _ = BACKEND.set(backend);
+    match backend {
+        Backend::Hyprland => {}
+        Backend::Sway => return Arc::new(crate::sway::SwayBackend::from_env(timeout)),
+        Backend::Wlr => return Arc::new(crate::wlr::WlrBackend::from_env(timeout)),
+    }
+    if let Some(instance) = instance {
+        let _ = INSTANCE.set(instance);
+    }
+    Arc::new(AutoBackend {
+        socket: SocketBackend::detect(timeout),
+        process: ProcessBackend { timeout },
+    })
+}
+
+/// A compositor for tests: queries are answered from canned replies and
+/// dispatches are recorded instead of sent.
+#[cfg(test)]
+pub mod fake {
+    use super::Compositor;
+    use anyhow::{anyhow, Result};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    pub struct FakeCompositor {
+        replies: Mutex<HashMap<String, String>>,
+        dispatched: Mutex<Vec<String>>,
+    }
+
+    impl FakeCompositor {
+        /// Answers `command` with `json` from now on.
+        pub fn reply(&self, command: &str, json: serde_json::Value) -> &Self {
+            self.replies
+                .lock()
+                .unwrap()
+                .insert(command.to_string(), json.to_string());
+            self
+        }
+
+        /// Returns the dispatches sent so far, in order.
+        pub fn dispatched(&self) -> Vec<String> {
+            self.dispatched.lock().unwrap().clone()
+        }
+    }
+
+    impl Compositor for FakeCompositor {
+        fn query(&self, command: &str) -> Result<Vec<u8>> {
+            let replies = self.replies.lock().unwrap();
+            let reply = replies
+                .get(command)
+                .ok_or_else(|| anyhow!("no reply for '{}'", command))?;
+            Ok(reply.clone().into_bytes())
+        }
+
+        fn dispatch(&self, command: &str) -> Result<String> {
+            self.dispatched.lock().unwrap().push(command.to_string());
+            Ok("ok".to_string())
+        }
+
+        fn dispatch_batch(&self, commands: &[&str]) -> Result<String> {
+            let mut dispatched = self.dispatched.lock().unwrap();
+            dispatched.extend(commands.iter().map(|c| c.to_string()));
+            Ok("ok".repeat(commands.len()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    /// Hyprland's backends on a socket that takes requests but never answers.
+    fn silent_socket(name: &str) -> (UnixListener, AutoBackend) {
+        let path = std::env::temp_dir().join(format!(
+            "hyprland-minimizer-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let backend = AutoBackend {
+            socket: Some(SocketBackend {
+                path,
+                timeout: TIMEOUT,
+            }),
+            process: ProcessBackend { timeout: TIMEOUT },
+        };
+        (listener, backend)
+    }
+
+    #[test]
+    fn dispatches_the_socket_took_are_not_sent_again() {
+        let (_listener, backend) = silent_socket("took");
+        let error = backend.dispatch("pin address:0x1").unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to read Hyprland's reply"));
+        let error = backend.dispatch_batch(&["pin address:0x1"]).unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to read Hyprland's reply"));
+        let _ = std::fs::remove_file(&backend.socket.unwrap().path);
+    }
+
+    #[test]
+    fn dispatches_the_socket_did_not_take_go_through_hyprctl() {
+        let (listener, backend) = silent_socket("refused");
+        drop(listener);
+        let _ = std::fs::remove_file(&backend.socket.as_ref().unwrap().path);
+        // hyprctl is not available here, so its error is what comes back.
+        let error = backend.dispatch("pin address:0x1").unwrap_err();
+        assert!(!format!("{:#}", error).contains("Failed to connect"));
+    }
+}
@@ -0,0 +1,88 @@
+//! A small `log` backend writing to stderr or a log file.
+//! Level prefixes are colored when writing to a terminal, and never in files.
+
+use anyhow::{Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+struct Logger {
+    color: bool,
+    file: Option<Mutex<File>>,
+}
+
+impl Logger {
+    fn prefix(&self, level: Level) -> String {
+        let name = level.as_str();
+        if !self.color {
+            return name.to_string();
+        }
+        let code = match level {
+            Level::Error => "31",
+            Level::Warn => "33",
+            Level::Info => "32",
+            Level::Debug => "34",
+            Level::Trace => "2",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, name)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{} {}\n", self.prefix(record.level()), record.args());
+        match &self.file {
+            Some(file) => {
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.write_all(line.as_bytes());
+                }
+            }
+            None => {
+                let _ = std::io::stderr().write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(Ok(mut file)) = self.file.as_ref().map(Mutex::lock) {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Returns true if log output to stderr should be colored.
+/// Color is used only on a terminal and can be turned off with `--no-color` or `NO_COLOR`.
+fn use_color(no_color: bool) -> bool {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    !no_color && !no_color_env && std::io::stderr().is_terminal()
+}
+
+/// Installs the logger. With a `log_file`, messages are appended to it instead of stderr.
+pub fn init(level: LevelFilter, no_color: bool, log_file: Option<&Path>) -> Result<()> {
+    let file = match log_file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?,
+        )),
+        None => None,
+    };
+    let logger = Logger {
+        color: file.is_none() && use_color(no_color),
+        file,
+    };
+    log::set_boxed_logger(Box::new(logger)).context("Failed to install logger")?;
+    log::set_max_level(level);
+    Ok(())
+}
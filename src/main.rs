@@ -2,552 +2,3105 @@
 //! Place this file in the `src/` directory of your Rust project.
 
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
-use futures_util::stream::StreamExt;
-use serde::Deserialize;
-use std::collections::HashMap;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use log::{debug, error, info, warn, LevelFilter};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::process::Command;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::sync::Notify;
-use tokio::time::{interval, Duration};
-use zbus::zvariant::{ObjectPath, Value};
-use zbus::{dbus_interface, ConnectionBuilder, Proxy};
+use tokio::time::{interval, Duration, Instant};
+
+mod completions;
+mod config;
+mod control;
+mod daemon;
+mod dbus;
+mod events;
+mod icon;
+mod ipc;
+mod logging;
+mod metrics;
+mod module;
+mod quirks;
+mod service;
+mod state;
+mod sway;
+mod wlr;
+
+use dbus::{DbusMenu, ItemStatus, StatusNotifierItem};
+use ipc::Compositor;
+use quirks::TrayQuirks;
 
 // --- Command-Line Interface Definition ---
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// The address of the window to minimize. If not provided, minimizes the active window.
     window_address: Option<String>,
+
+    /// Minimize the Nth most recently focused window instead (0 is the focused one).
+    #[arg(long, value_name = "N", conflicts_with = "window_address")]
+    match_focused_history: Option<i32>,
+
+    /// Minimize a window whose class matches this regex instead, preferring the focused one.
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex, conflicts_with_all = ["window_address", "match_focused_history"])]
+    class: Option<Regex>,
+
+    /// Minimize a window whose title contains this text instead, preferring the focused one.
+    /// Patterns containing regex metacharacters are matched as a regex.
+    #[arg(long, value_name = "PATTERN", conflicts_with_all = ["window_address", "match_focused_history", "class"])]
+    title: Option<String>,
+
+    /// With --class, minimize every matching window, each with its own tray icon.
+    #[arg(long, requires = "class")]
+    all: bool,
+
+    /// Minimize the window of the process with this pid instead. The window address wins if both are given.
+    #[arg(long, value_name = "PID", conflicts_with_all = ["match_focused_history", "class", "title"])]
+    pid: Option<i32>,
+
+    /// Where to send the window when the tray icon is left-clicked. A comma
+    /// separated list (e.g. `original,current`) is tried in order until one works.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "current")]
+    restore_to: Vec<RestoreTarget>,
+
+    /// Restore to the active workspace of the monitor with this name (e.g. DP-1) instead.
+    #[arg(long, value_name = "NAME")]
+    restore_monitor_name: Option<String>,
+
+    /// Restore the window to the current workspace as soon as it gets flagged as urgent.
+    #[arg(long)]
+    restore_on_urgent: bool,
+
+    /// Rename the special workspace to show how many windows are minimized, e.g. "Minimized (3)".
+    /// The label starts with the capitalized --special-workspace name.
+    #[arg(long)]
+    rename_workspace: bool,
+
+    /// After restoring the window, minimize all other windows on its workspace.
+    #[arg(long)]
+    solo_on_restore: bool,
+
+    /// Listen for commands (e.g. `metrics`) on a Unix socket at this path.
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<std::path::PathBuf>,
+
+    /// Milliseconds to wait before re-registering with a restarted tray.
+    /// Defaults to a value suited to the desktop in `XDG_CURRENT_DESKTOP`.
+    #[arg(long, value_name = "MS")]
+    registration_delay: Option<u64>,
+
+    /// Bus name of the StatusNotifierWatcher to register with. By default both the
+    /// KDE and the freedesktop name are tried.
+    #[arg(long, value_name = "NAME")]
+    watcher_name: Option<String>,
+
+    /// Ask the tray to show the menu on left-click instead of restoring the window.
+    /// Middle-click keeps running --middle-action.
+    #[arg(long)]
+    menu_on_click: bool,
+
+    /// Run as a daemon that keeps the tray icons of all windows minimized through it.
+    /// Later invocations hand their window over to the daemon, which applies its
+    /// own options to it.
+    #[arg(long)]
+    daemon: bool,
+
+    /// Print a status line for a Waybar custom module instead of showing tray icons.
+    /// SIGUSR1 restores the most recent minimized window, SIGUSR2 all of them.
+    #[arg(long, conflicts_with = "daemon")]
+    module: bool,
+
+    /// Socket the daemon listens on. Defaults to `$XDG_RUNTIME_DIR/hyprland-minimizer.sock`.
+    #[arg(long, value_name = "PATH")]
+    daemon_socket: Option<std::path::PathBuf>,
+
+    /// Milliseconds to wait for a reply to outgoing D-Bus calls, e.g. to the tray watcher.
+    #[arg(long, value_name = "MS", default_value_t = 5000)]
+    dbus_timeout: u64,
+
+    /// Prefix of the bus name each minimizer takes; `.p<pid>` gets appended.
+    #[arg(long, value_name = "NAME", default_value = "org.kde.StatusNotifierItem.minimizer", value_parser = parse_bus_name_prefix)]
+    bus_name_prefix: String,
+
+    /// Object path the tray item is served at. Items sharing a connection get numbered paths below it.
+    #[arg(long, value_name = "PATH", default_value = "/StatusNotifierItem", value_parser = parse_object_path)]
+    item_path: String,
+
+    /// Object path the tray menu is served at.
+    #[arg(long, value_name = "PATH", default_value = "/Menu", value_parser = parse_object_path)]
+    menu_path: String,
+
+    /// Milliseconds to wait for the tray watcher to appear at startup, e.g. while
+    /// the bar is still starting after login.
+    #[arg(long, value_name = "MS", default_value_t = 10000)]
+    watcher_wait: u64,
+
+    /// How often to retry a Hyprland request that failed, e.g. during a compositor
+    /// reload, with exponential backoff starting at 100ms. Toggles such as `pin`
+    /// are sent once, as a retry could undo them.
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    hyprctl_retries: u32,
+
+    /// Milliseconds a single Hyprland request may take before it is given up.
+    #[arg(long, value_name = "MS", default_value_t = 5000)]
+    hyprctl_timeout: u64,
+
+    /// Signature of the Hyprland instance to talk to, for nested or parallel
+    /// sessions. Defaults to `HYPRLAND_INSTANCE_SIGNATURE`.
+    #[arg(long, value_name = "SIGNATURE")]
+    instance: Option<String>,
+
+    /// The compositor to talk to. On Sway, minimized windows go to the scratchpad;
+    /// `wlr` minimizes them through wlr-foreign-toplevel-management and knows only
+    /// one workspace. Hyprland-only dispatches such as `--rename-workspace` fail there.
+    #[arg(long, value_enum, default_value = "hyprland")]
+    backend: ipc::Backend,
+
+    /// Name of the special workspace minimized windows are moved to, without the `special:` prefix.
+    #[arg(
+        long,
+        visible_alias = "workspace-name",
+        value_name = "NAME",
+        default_value = "minimized",
+        value_parser = parse_workspace_name
+    )]
+    special_workspace: String,
+
+    /// Milliseconds between window state checks when the event socket is unavailable.
+    #[arg(long, value_name = "MS", default_value_t = 2000, value_parser = clap::value_parser!(u64).range(100..))]
+    poll_interval: u64,
+
+    /// Print the dispatches that would be sent to Hyprland instead of sending them.
+    /// Queries still run, so the window is looked up as usual and the tray icon works.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Log more: `-v` for what the minimizer does, `-vv` for every D-Bus call.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only log errors.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Never color log output, even on a terminal. `NO_COLOR` is honored as well.
+    #[arg(long)]
+    no_color: bool,
+
+    /// Append log messages to this file instead of stderr.
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Print a completion script for this shell and exit.
+    #[arg(long, value_name = "SHELL", value_enum)]
+    generate_completions: Option<completions::Shell>,
+
+    /// Label of the menu item restoring to the current workspace.
+    /// Templates may use the placeholders {title} and {workspace}.
+    #[arg(long, value_name = "TEMPLATE", default_value = "Open {title}", value_parser = parse_label_template)]
+    menu_open_label: String,
+
+    /// Label of the menu item restoring to the original workspace.
+    #[arg(long, value_name = "TEMPLATE", default_value = "Open on original workspace ({workspace})", value_parser = parse_label_template)]
+    menu_original_label: String,
+
+    /// Label of the single restore item shown with --compact-menu.
+    #[arg(long, value_name = "TEMPLATE", default_value = "Restore {title}", value_parser = parse_label_template)]
+    menu_restore_label: String,
+
+    /// Label of the menu item closing the window.
+    #[arg(long, value_name = "TEMPLATE", default_value = "Close {title}", value_parser = parse_label_template)]
+    menu_close_label: String,
+
+    /// Close the window (instead of restoring it) after it has been minimized for this many seconds.
+    #[arg(long, value_name = "SECS")]
+    auto_close_after: Option<u64>,
+
+    /// What a left click on the tray icon does.
+    #[arg(long, value_enum, value_name = "ACTION", default_value = "restore")]
+    left_action: ClickAction,
+
+    /// What a middle click on the tray icon does.
+    #[arg(long, value_enum, value_name = "ACTION", default_value = "close")]
+    middle_action: ClickAction,
+
+    /// Ask for confirmation in a notification before closing the window from the tray icon.
+    #[arg(long)]
+    confirm_close: bool,
+
+    /// Don't show a desktop notification when a window gets minimized.
+    #[arg(long)]
+    no_notify: bool,
+
+    /// Restore the window by itself after it has been minimized for this many seconds.
+    /// 0 keeps it minimized until the tray icon is used.
+    #[arg(long, value_name = "SECS")]
+    restore_after: Option<u64>,
+
+    /// Whether to put the window back on its original workspace when the minimizer exits.
+    #[arg(long, value_enum, value_name = "MODE", default_value = "on-signal")]
+    restore_on_exit: RestoreOnExit,
+
+    /// Mark the tray icon as passive after the window has been minimized this many
+    /// seconds without changing its title or asking for attention.
+    #[arg(long, value_name = "SECS")]
+    passive_after: Option<u64>,
+
+    /// Mark the tray icon as passive while the special workspace is shown on a
+    /// monitor, where the window is in plain view anyway.
+    #[arg(long)]
+    passive_while_shown: bool,
+
+    /// Developer tool: minimize and restore the window this many times, print
+    /// timing stats and exit without creating a tray icon.
+    #[arg(long, value_name = "N")]
+    exit_after: Option<u32>,
+
+    /// Minimize the active window even if it is not on the active workspace.
+    #[arg(long)]
+    force: bool,
+
+    /// Leave focus wherever Hyprland puts it after minimizing, instead of on the
+    /// previously focused window.
+    #[arg(long)]
+    no_refocus: bool,
+
+    /// Restore the window instead if it is already minimized, so one keybind does both.
+    #[arg(long)]
+    toggle: bool,
+
+    /// Show a two-item menu: "Restore" (following --restore-to) and "Close".
+    #[arg(long)]
+    compact_menu: bool,
+
+    /// Use this PNG file as the tray icon instead of looking one up for the window class.
+    /// Only PNG is supported; SVG files are rejected at startup.
+    #[arg(long, value_name = "PATH", value_parser = parse_icon_path)]
+    icon: Option<std::path::PathBuf>,
+
+    /// Extra directory the tray looks up the icon named after the window class in,
+    /// for apps that install their icons outside the icon theme.
+    #[arg(long, value_name = "DIR")]
+    icon_theme_path: Option<std::path::PathBuf>,
+
+    /// Mark XWayland windows with "(X11)" in the tray tooltip.
+    #[arg(long)]
+    show_xwayland: bool,
+
+    /// Keep the previously focused window focused when restoring, instead of focusing the restored one.
+    #[arg(long)]
+    restore_focus_previous: bool,
+
+    /// Bring the window back without focusing it or switching to its workspace.
+    #[arg(long, conflicts_with = "restore_focus_previous")]
+    silent_restore: bool,
+
+    /// Remember the workspace a window of this class gets moved to by hand,
+    /// and restore windows of the class there in the future.
+    #[arg(long)]
+    learn_restore_target: bool,
+
+    /// How to recognize the minimized window. `identity` follows it to a new
+    /// address when it gets recreated, e.g. on reparenting, going by pid and initial class.
+    #[arg(long, value_enum, value_name = "HOW", default_value = "address")]
+    track_by: TrackBy,
+}
+
+/// Placeholders that may be used in menu label templates.
+const LABEL_PLACEHOLDERS: [&str; 2] = ["title", "workspace"];
+
+/// Checks that a menu label template only uses known placeholders.
+fn parse_label_template(template: &str) -> Result<String, String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in label template '{}'", template))?;
+        let name = &rest[start + 1..start + end];
+        if !LABEL_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder '{{{}}}', expected one of {{title}} or {{workspace}}",
+                name
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(template.to_string())
+}
+
+/// Checks that the icon file can be served, so a bad path fails at startup
+/// instead of showing a blank icon.
+fn parse_icon_path(path: &str) -> Result<std::path::PathBuf, String> {
+    let path = std::path::PathBuf::from(path);
+    icon::load(&path).map_err(|e| format!("{:#}", e))?;
+    Ok(path)
+}
+
+fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+/// Checks that bus names built from the prefix are valid well-known names.
+fn parse_bus_name_prefix(prefix: &str) -> Result<String, String> {
+    zbus::names::WellKnownName::try_from(format!("{}.p1", prefix))
+        .map(|_| prefix.to_string())
+        .map_err(|e| format!("'{}' is not a valid bus name prefix: {}", prefix, e))
+}
+
+/// Checks that a D-Bus object path is well-formed, e.g. `/StatusNotifierItem`.
+fn parse_object_path(path: &str) -> Result<String, String> {
+    zbus::zvariant::ObjectPath::try_from(path)
+        .map(|_| path.to_string())
+        .map_err(|e| format!("'{}' is not a valid object path: {}", path, e))
+}
+
+/// Checks that a special workspace name fits into a dispatch unchanged.
+fn parse_workspace_name(name: &str) -> Result<String, String> {
+    if name.is_empty() {
+        return Err("the workspace name must not be empty".to_string());
+    }
+    if name.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(format!(
+            "'{}' contains spaces or commas, which would break the dispatch",
+            name
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Fills in the placeholders of a menu label template.
+fn render_label(template: &str, window_info: &WindowInfo) -> String {
+    template
+        .replace("{title}", &window_info.title)
+        .replace("{workspace}", &window_info.workspace.id.to_string())
+}
+
+impl Args {
+    /// Returns the log level selected by `--verbose` and `--quiet`. Warnings and
+    /// errors only by default, to keep the journal quiet when run as a service.
+    fn log_level(&self) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::Error;
+        }
+        match self.verbose {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// Restores the window the way a left-click does, trying each `--restore-to`
+    /// target in order until one succeeds.
+    fn restore(
+        &self,
+        compositor: &dyn Compositor,
+        window_info: &WindowInfo,
+        click: Option<(i32, i32)>,
+    ) -> Result<()> {
+        if let Some(name) = &self.restore_monitor_name {
+            let monitor = get_monitor_by_name(compositor, name)?;
+            info!(
+                "Restoring to workspace {} on monitor '{}'",
+                monitor.active_workspace.id, monitor.name
+            );
+            return restore_window(compositor, window_info, monitor.active_workspace.id, self);
+        }
+
+        let mut last_error = anyhow!("No restore target given.");
+        for target in &self.restore_to {
+            match target
+                .resolve(compositor, window_info, click)
+                .and_then(|id| restore_window(compositor, window_info, id, self))
+            {
+                Ok(()) => {
+                    info!("Restored using target {:?}", target);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("Restore target {:?} failed: {}", target, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Returns the bus name of this process.
+    fn bus_name(&self) -> String {
+        format!("{}.p{}", self.bus_name_prefix, std::process::id())
+    }
+
+    /// Returns the tray quirks for the current desktop with any explicit overrides applied.
+    fn tray_quirks(&self) -> TrayQuirks {
+        let mut quirks = TrayQuirks::detect();
+        if let Some(delay) = self.registration_delay {
+            quirks.registration_delay = Duration::from_millis(delay);
+        }
+        if let Some(name) = &self.watcher_name {
+            quirks.watcher_names = vec![name.clone()];
+        }
+        if self.menu_on_click {
+            quirks.item_is_menu = true;
+        }
+        quirks
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Exercise the tray menu in-process with a sample window and print what it serves.
+    /// Meant for developers working on the menu; needs no D-Bus session, but
+    /// reads the workspaces from Hyprland. Clicks only print their dispatches.
+    ProbeDbusmenu,
+
+    /// Restore minimized windows matching a selector to the current workspace.
+    Restore {
+        /// Addresses of minimized windows to restore.
+        addresses: Vec<String>,
+
+        /// Restore all minimized windows with this class.
+        #[arg(long)]
+        class: Option<String>,
+    },
+
+    /// Print the minimized windows as a JSON array of objects with the keys
+    /// `address`, `title` and `class`, e.g. to build a picker with rofi.
+    List,
+
+    /// Minimize every window on the current workspace except the focused one.
+    FocusMode {
+        /// Restore the windows a previous `focus-mode` minimized.
+        #[arg(long)]
+        off: bool,
+    },
+
+    /// Write systemd user units that start the daemon when the first window gets
+    /// minimized, through socket activation.
+    InstallService {
+        /// Where to write the units. Defaults to `$XDG_CONFIG_HOME/systemd/user`.
+        #[arg(long, value_name = "DIR")]
+        dir: Option<std::path::PathBuf>,
+    },
+}
+
+/// The workspace a window is sent to when it gets restored.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RestoreTarget {
+    /// The active workspace of the focused monitor.
+    Current,
+    /// The workspace the window was minimized from.
+    Original,
+    /// The active workspace of the monitor the mouse cursor is on.
+    MouseMonitor,
+    /// The active workspace of the monitor the tray icon was clicked on.
+    /// Falls back to the cursor's monitor when the tray sends no coordinates.
+    ClickMonitor,
+}
+
+/// What a click on the tray icon does.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ClickAction {
+    /// Restore to the learned workspace or the targets given by --restore-to.
+    Restore,
+    /// Restore to the active workspace.
+    RestoreCurrent,
+    /// Restore to the workspace the window was minimized from.
+    RestoreOriginal,
+    /// Close the window.
+    Close,
+    /// Ignore the click.
+    Nothing,
+}
+
+/// How the minimized window is recognized while it is parked.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TrackBy {
+    /// By its address only; the window is gone once the address is.
+    Address,
+    /// By its address, or else a window of the same pid and initial class.
+    Identity,
+}
+
+/// When the window goes back to its original workspace as the minimizer exits.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RestoreOnExit {
+    /// On every exit, including after a crash or when the icon went away for another reason.
+    Always,
+    /// Never; the window stays on the special workspace, e.g. for a daemon to take over.
+    Never,
+    /// When interrupted by Ctrl+C, SIGTERM or SIGHUP, or after a crash.
+    OnSignal,
 }
 
 // --- Hyprland Data Structures ---
 // These structs are used to deserialize the JSON output from `hyprctl`.
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Default)]
 struct Workspace {
     id: i32,
+    #[serde(default)]
+    name: String,
+}
+
+/// An entry of `hyprctl workspaces`.
+#[derive(Deserialize, Debug, Clone)]
+struct WorkspaceSummary {
+    id: i32,
+    name: String,
+    windows: u32,
+    /// Missing on old Hyprland versions.
+    #[serde(rename = "monitorID", default)]
+    monitor_id: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Monitor {
+    id: i32,
+    name: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    scale: f64,
+    transform: i32,
+    #[serde(rename = "activeWorkspace")]
+    active_workspace: Workspace,
+    /// The special workspace shown on top, with id 0 if none is.
+    #[serde(rename = "specialWorkspace", default)]
+    special_workspace: Workspace,
+    #[serde(default)]
+    focused: bool,
+}
+
+impl Monitor {
+    /// Returns true if the given point in global layout coordinates lies on this monitor.
+    fn contains(&self, x: i32, y: i32) -> bool {
+        // hyprctl reports the mode in physical pixels, but positions are in
+        // logical (scaled) coordinates. Rotated monitors swap width and height.
+        let (mut width, mut height) = (
+            (self.width as f64 / self.scale).round() as i32,
+            (self.height as f64 / self.scale).round() as i32,
+        );
+        if self.transform % 2 == 1 {
+            std::mem::swap(&mut width, &mut height);
+        }
+        x >= self.x && x < self.x + width && y >= self.y && y < self.y + height
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
+struct CursorPos {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
 #[allow(dead_code)]
 struct WindowInfo {
     address: String,
     workspace: Workspace,
     title: String,
     class: String,
+    pid: i32,
+    pinned: bool,
+    /// Whether the window is an X11 client running through XWayland.
+    xwayland: bool,
+    /// Id of the monitor the window is on; hyprctl reports ids, not names.
+    monitor: i32,
+    floating: bool,
+    /// Position of the top-left corner in global layout coordinates.
+    at: [i32; 2],
+    size: [i32; 2],
+    /// 0 for a normal window, 1 maximized, 2 fullscreen, 3 both.
+    #[serde(deserialize_with = "deserialize_fullscreen")]
+    fullscreen: i32,
+    /// Position in the focus history: 0 is the focused window, 1 the one before, and so on.
+    #[serde(rename = "focusHistoryID")]
+    focus_history_id: i32,
+    /// Addresses of the windows in the window's group, itself included; empty if ungrouped.
+    #[serde(default)]
+    grouped: Vec<String>,
+    /// The class the window was mapped with, which unlike `class` never changes.
+    #[serde(rename = "initialClass", default)]
+    initial_class: String,
+    /// The title the window was mapped with.
+    #[serde(rename = "initialTitle", default)]
+    initial_title: String,
+}
+
+impl WindowInfo {
+    /// Returns the class to look up the window's icon by: the initial one, as
+    /// some apps change their class at runtime, or else the current one.
+    fn icon_class(&self) -> &str {
+        if self.initial_class.is_empty() {
+            &self.class
+        } else {
+            &self.initial_class
+        }
+    }
+}
+
+/// Reads the `fullscreen` field, which Hyprland before 0.42 reported as a bool.
+fn deserialize_fullscreen<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<i32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Fullscreen {
+        Legacy(bool),
+        Mode(i32),
+    }
+    Ok(match Fullscreen::deserialize(deserializer)? {
+        Fullscreen::Legacy(true) => 2,
+        Fullscreen::Legacy(false) => 0,
+        Fullscreen::Mode(mode) => mode,
+    })
 }
 
 // --- Hyprland Interaction Functions ---
 
+/// Set from `--hyprctl-retries` at startup.
+static HYPRCTL_RETRIES: OnceLock<u32> = OnceLock::new();
+
+/// The delay before the first retry of a failed Hyprland request; it doubles with each retry.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Dispatchers that flip a state each time they run. A failed request may have
+/// run anyway, e.g. when only the reply timed out, and a retry would undo it.
+const TOGGLE_DISPATCHERS: &[&str] = &[
+    "pin",
+    "togglespecialworkspace",
+    "fullscreen",
+    "togglefloating",
+    "togglegroup",
+];
+
+/// Returns true if the dispatches can be sent again after a failed request,
+/// i.e. none of them is a toggle.
+fn repeatable(commands: &[&str]) -> bool {
+    !commands.iter().any(|command| {
+        let dispatcher = command.split(' ').next().unwrap_or_default();
+        TOGGLE_DISPATCHERS.contains(&dispatcher)
+    })
+}
+
+/// Runs a Hyprland request, retrying failures with exponential backoff if it
+/// is `repeatable`.
+///
+/// Requests and the waits between them block. Called from a task, the worker
+/// first hands its other tasks to the rest of the runtime, so a request waiting
+/// out its retries doesn't hold them up. D-Bus handlers send their requests
+/// from the blocking pool instead, see `dbus::run_blocking`.
+fn with_retries<T>(what: &str, repeatable: bool, request: impl FnMut() -> Result<T>) -> Result<T> {
+    let retries = if repeatable {
+        HYPRCTL_RETRIES.get().copied().unwrap_or(0)
+    } else {
+        0
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) if runtime.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| retry(what, retries, request))
+        }
+        _ => retry(what, retries, request),
+    }
+}
+
+fn retry<T>(what: &str, retries: u32, mut request: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut delay = RETRY_BASE_DELAY;
+    let mut attempt = 0;
+    loop {
+        match request() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries => {
+                debug!(
+                    "hyprctl '{}' failed ({:#}), retrying in {}ms",
+                    what,
+                    e,
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 /// Executes a hyprctl command and returns the parsed JSON output.
-fn hyprctl<T: for<'de> Deserialize<'de>>(command: &str) -> Result<T> {
-    let output = Command::new("hyprctl")
-        .arg("-j")
-        .arg(command)
-        .output()
-        .with_context(|| format!("Failed to execute hyprctl command: {}", command))?;
+fn hyprctl<T: for<'de> Deserialize<'de>>(compositor: &dyn Compositor, command: &str) -> Result<T> {
+    with_retries(command, true, || compositor.query_json(command))
+}
+
+/// A dispatch that Hyprland rejected, with the reply it gave.
+#[derive(Debug)]
+struct DispatchError {
+    command: String,
+    reply: String,
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("hyprctl command '{}' failed: {}", command, stderr);
+impl std::fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hyprctl dispatch command '{}' failed: {}",
+            self.command, self.reply
+        )
     }
+}
+
+impl std::error::Error for DispatchError {}
 
-    serde_json::from_slice(&output.stdout)
-        .with_context(|| format!("Failed to parse JSON from hyprctl command: {}", command))
+impl DispatchError {
+    /// Returns true if Hyprland does not know the dispatcher at all.
+    fn is_invalid_dispatcher(&self) -> bool {
+        self.reply
+            .to_ascii_lowercase()
+            .contains("invalid dispatcher")
+    }
 }
 
+/// Set by `--dry-run`: dispatches are printed instead of sent.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
 /// Executes a hyprctl dispatch command.
-fn hyprctl_dispatch(command: &str) -> Result<()> {
-    let status = Command::new("hyprctl")
-        .arg("dispatch")
-        .arg(command)
-        .status()
-        .with_context(|| format!("Failed to execute hyprctl dispatch: {}", command))?;
+fn hyprctl_dispatch(compositor: &dyn Compositor, command: &str) -> Result<()> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        println!("[Dry run] hyprctl dispatch {}", command);
+        return Ok(());
+    }
+    // Rejected dispatches still get a reply; only `ok` means it was accepted.
+    let reply = with_retries(command, repeatable(&[command]), || {
+        compositor.dispatch(command)
+    })?;
+    if !(reply.is_empty() || reply == "ok") {
+        return Err(DispatchError {
+            command: command.to_string(),
+            reply,
+        }
+        .into());
+    }
+    Ok(())
+}
 
-    if !status.success() {
-        anyhow::bail!("hyprctl dispatch command '{}' failed", command);
+/// Executes several hyprctl dispatch commands in one request, so nothing else
+/// gets to run in between. Stops at the first command Hyprland rejected.
+fn hyprctl_dispatch_batch(compositor: &dyn Compositor, commands: &[&str]) -> Result<()> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        for command in commands {
+            hyprctl_dispatch(compositor, command)?;
+        }
+        return Ok(());
+    }
+    let what = commands.join(" ; ");
+    let reply = with_retries(&what, repeatable(commands), || {
+        compositor.dispatch_batch(commands)
+    })?;
+    // The replies are run together, so count off one `ok` per accepted command.
+    let mut rest = reply.trim();
+    for command in commands {
+        if rest.is_empty() {
+            break;
+        }
+        match rest.strip_prefix("ok") {
+            Some(after) => rest = after.trim_start(),
+            None => {
+                return Err(DispatchError {
+                    command: command.to_string(),
+                    reply: rest.to_string(),
+                }
+                .into())
+            }
+        }
     }
     Ok(())
 }
 
-/// Finds a window by its address from the list of all clients.
-fn get_window_by_address(address: &str) -> Result<WindowInfo> {
+/// Class used by any window the minimizer itself creates. Windows with this
+/// class (or a class starting with `hyprland-minimizer-`) are never minimized.
+const APP_ID: &str = "hyprland-minimizer";
+
+/// Returns true if the window belongs to the minimizer itself.
+fn is_own_window(window_info: &WindowInfo) -> bool {
+    window_info
+        .class
+        .strip_prefix(APP_ID)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+}
+
+/// Returns all clients that are eligible for minimizing.
+/// All window selection should go through here so our own windows are always skipped.
+fn get_clients(compositor: &dyn Compositor) -> Result<Vec<WindowInfo>> {
     let clients: Vec<WindowInfo> =
-        hyprctl("clients").context("Failed to get client list from Hyprland.")?;
-    clients
+        hyprctl(compositor, "clients").context("Failed to get client list from Hyprland.")?;
+    Ok(clients.into_iter().filter(|c| !is_own_window(c)).collect())
+}
+
+/// Finds a window by its address from the list of all clients.
+fn get_window_by_address(compositor: &dyn Compositor, address: &str) -> Result<WindowInfo> {
+    get_clients(compositor)?
         .into_iter()
         .find(|c| c.address == address)
         .ok_or_else(|| anyhow!("Could not find a window with address '{}'", address))
 }
 
-// --- D-Bus Menu Implementation ---
-
-struct DbusMenu {
-    window_info: WindowInfo,
-    exit_notify: Arc<Notify>,
+/// Returns the window whose class matches the regex, preferring the focused one.
+fn get_window_by_class(compositor: &dyn Compositor, pattern: &Regex) -> Result<WindowInfo> {
+    let matching = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| pattern.is_match(&c.class))
+        .collect();
+    pick_preferring_focused(matching, &format!("class matching '{}'", pattern))
 }
 
-#[dbus_interface(name = "com.canonical.dbusmenu")]
-impl DbusMenu {
-    /// Returns the menu layout.
-    fn get_layout(
-        &self,
-        _parent_id: i32,
-        _recursion_depth: i32,
-        _property_names: Vec<String>,
-    ) -> (u32, (i32, HashMap<String, Value>, Vec<Value>)) {
-        println!("[D-Bus Menu] GetLayout called.");
-
-        // Item ID 1: Open on current workspace
-        let mut open_props = HashMap::new();
-        open_props.insert("type".to_string(), Value::from("standard"));
-        open_props.insert(
-            "label".to_string(),
-            Value::from(format!("Open {}", self.window_info.title)),
-        );
-        let open_item = Value::from((1i32, open_props, Vec::<Value>::new()));
-
-        // Item ID 2: Open on original workspace
-        let mut last_ws_props = HashMap::new();
-        last_ws_props.insert("type".to_string(), Value::from("standard"));
-        last_ws_props.insert(
-            "label".to_string(),
-            Value::from(format!(
-                "Open on original workspace ({})",
-                self.window_info.workspace.id
-            )),
-        );
-        let last_ws_item = Value::from((2i32, last_ws_props, Vec::<Value>::new()));
-
-        // Item ID 3: Close the window
-        let mut close_props = HashMap::new();
-        close_props.insert("type".to_string(), Value::from("standard"));
-        close_props.insert(
-            "label".to_string(),
-            Value::from(format!("Close {}", self.window_info.title)),
-        );
-        let close_item = Value::from((3i32, close_props, Vec::<Value>::new()));
-
-        // The root of the menu layout
-        let mut root_props = HashMap::new();
-        root_props.insert("children-display".to_string(), Value::from("submenu"));
-
-        let root_layout = (
-            0i32, // Root node ID is always 0
-            root_props,
-            vec![open_item, last_ws_item, close_item],
-        );
+/// Returns the window whose title contains the pattern, preferring the focused one.
+/// Patterns with regex metacharacters are matched as a regex instead.
+fn get_window_by_title(compositor: &dyn Compositor, pattern: &str) -> Result<WindowInfo> {
+    let regex = if pattern.contains(|c| "^$*+?()[]{}|\\".contains(c)) {
+        Regex::new(pattern).ok()
+    } else {
+        None
+    };
+    let matching = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| match &regex {
+            Some(regex) => regex.is_match(&c.title),
+            None => c.title.contains(pattern),
+        })
+        .collect();
+    pick_preferring_focused(matching, &format!("title matching '{}'", pattern))
+}
 
-        // Incrementing the revision number helps ensure clients fetch the new layout
-        let revision = 2u32;
-        println!(
-            "[D-Bus Menu] Serving layout revision {}: {:?}",
-            revision, root_layout
-        );
-        (revision, root_layout)
+/// Picks one of several matching windows. When there are multiple, they are
+/// listed as a warning and the focused one wins, falling back to the first match.
+fn pick_preferring_focused(mut matching: Vec<WindowInfo>, what: &str) -> Result<WindowInfo> {
+    if matching.is_empty() {
+        anyhow::bail!("No window has a {}", what);
     }
-
-    /// Returns the properties for a group of menu items.
-    fn get_group_properties(
-        &self,
-        ids: Vec<i32>,
-        _property_names: Vec<String>,
-    ) -> Vec<(i32, HashMap<String, Value>)> {
-        println!("[D-Bus Menu] GetGroupProperties called for IDs: {:?}", ids);
-        let mut result = Vec::new();
-        for id in ids {
-            let mut props = HashMap::new();
-            let label = match id {
-                1 => format!("Open {}", self.window_info.title),
-                2 => format!(
-                    "Open on original workspace ({})",
-                    self.window_info.workspace.id
-                ),
-                3 => format!("Close {}", self.window_info.title),
-                _ => continue,
-            };
-            props.insert("label".to_string(), Value::from(label));
-            props.insert("enabled".to_string(), Value::from(true));
-            props.insert("visible".to_string(), Value::from(true));
-            props.insert("type".to_string(), Value::from("standard"));
-            result.push((id, props));
+    if matching.len() > 1 {
+        warn!("{} windows have a {}:", matching.len(), what);
+        for c in &matching {
+            warn!("  {} {} ({})", c.address, c.title, c.class);
         }
-        println!("[D-Bus Menu] Returning properties: {:?}", result);
-        result
     }
+    let index = matching
+        .iter()
+        .position(|c| c.focus_history_id == 0)
+        .unwrap_or(0);
+    Ok(matching.swap_remove(index))
+}
 
-    /// Handles a batch of click events. This is called by Waybar instead of the singular `Event`.
-    fn event_group(&self, events: Vec<(i32, String, Value<'_>, u32)>) {
-        println!(
-            "[D-Bus Menu] EventGroup received with {} events",
-            events.len()
+/// Returns the window of the process with this pid, preferring the focused one.
+fn get_window_by_pid(compositor: &dyn Compositor, pid: i32) -> Result<WindowInfo> {
+    let matching: Vec<WindowInfo> = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| c.pid == pid)
+        .collect();
+    if matching.is_empty() {
+        anyhow::bail!(
+            "Process {} has no window. It may not have mapped one yet.",
+            pid
         );
-        for (id, event_id, data, timestamp) in events {
-            self.event(id, &event_id, data, timestamp);
-        }
     }
+    pick_preferring_focused(matching, &format!("pid {}", pid))
+}
 
-    /// Handles a single click event on a menu item.
-    fn event(&self, id: i32, event_id: &str, _data: Value<'_>, _timestamp: u32) {
-        println!(
-            "[D-Bus Menu] Event received: id='{}', event_id='{}'",
-            id, event_id
-        );
-        if event_id == "clicked" {
-            let res = match id {
-                1 => {
-                    // Open on current workspace
-                    println!("[D-Bus Menu] 'Open' action triggered.");
-                    match hyprctl::<Workspace>("activeworkspace") {
-                        Ok(active_workspace) => hyprctl_dispatch(&format!(
-                            "movetoworkspace {},address:{}",
-                            active_workspace.id, self.window_info.address
-                        ))
-                        .and_then(|_| {
-                            hyprctl_dispatch(&format!(
-                                "focuswindow address:{}",
-                                self.window_info.address
-                            ))
-                        }),
-                        Err(e) => {
-                            eprintln!("[Error] Failed to get active workspace: {}", e);
-                            Err(e)
-                        }
-                    }
-                }
-                2 => {
-                    // Open on original workspace
-                    println!("[D-Bus Menu] 'Open on original workspace' action triggered.");
-                    hyprctl_dispatch(&format!(
-                        "movetoworkspace {},address:{}",
-                        self.window_info.workspace.id, self.window_info.address
-                    ))
-                    .and_then(|_| {
-                        hyprctl_dispatch(&format!(
-                            "focuswindow address:{}",
-                            self.window_info.address
-                        ))
-                    })
-                }
-                3 => {
-                    // Close the window
-                    println!("[D-Bus Menu] 'Close' action triggered.");
-                    hyprctl_dispatch(&format!("closewindow address:{}", self.window_info.address))
-                }
-                _ => {
-                    println!("[D-Bus Menu] Clicked on unknown item id: {}", id);
-                    return;
-                }
-            };
+/// Finds the window at the given position in the focus history.
+fn get_window_by_focus_history(compositor: &dyn Compositor, index: i32) -> Result<WindowInfo> {
+    let clients = get_clients(compositor)?;
+    let max = clients.iter().map(|c| c.focus_history_id).max();
+    clients
+        .into_iter()
+        .find(|c| c.focus_history_id == index)
+        .ok_or_else(|| match max {
+            Some(max) => anyhow!(
+                "No window at focus history index {}; the history has indices 0 to {}",
+                index,
+                max
+            ),
+            None => anyhow!("No windows are open"),
+        })
+}
 
-            if let Err(e) = res {
-                eprintln!(
-                    "[Error] Failed to execute hyprctl dispatch from menu: {}",
-                    e
-                );
-            }
+/// Finds a connected monitor by its name, e.g. `DP-1`.
+fn get_monitor_by_name(compositor: &dyn Compositor, name: &str) -> Result<Monitor> {
+    let monitors: Vec<Monitor> =
+        hyprctl(compositor, "monitors").context("Failed to get monitor list from Hyprland.")?;
+    let connected: Vec<String> = monitors.iter().map(|m| m.name.clone()).collect();
+    monitors
+        .into_iter()
+        .find(|m| m.name == name)
+        .ok_or_else(|| {
+            anyhow!(
+                "Monitor '{}' is not connected. Connected monitors: {}",
+                name,
+                connected.join(", ")
+            )
+        })
+}
 
-            self.exit_notify.notify_one();
-        }
-    }
+/// Finds the monitor that currently contains the mouse cursor.
+fn get_monitor_under_cursor(compositor: &dyn Compositor) -> Result<Monitor> {
+    let cursor: CursorPos =
+        hyprctl(compositor, "cursorpos").context("Failed to get cursor position.")?;
+    get_monitor_at(compositor, cursor.x, cursor.y)
+}
 
-    /// Handles a batch of "about to show" requests.
-    fn about_to_show_group(&self, ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
-        println!("[D-Bus Menu] AboutToShowGroup received for IDs: {:?}", ids);
-        (vec![], vec![])
+/// Returns the monitor containing the given global layout coordinates.
+fn get_monitor_at(compositor: &dyn Compositor, x: i32, y: i32) -> Result<Monitor> {
+    if click_position(x, y).is_none() {
+        return Err(anyhow!("{},{} is not a usable position", x, y));
     }
+    let monitors: Vec<Monitor> =
+        hyprctl(compositor, "monitors").context("Failed to get monitor list from Hyprland.")?;
+    monitors
+        .into_iter()
+        .find(|m| m.contains(x, y))
+        .ok_or_else(|| anyhow!("No monitor contains the position {},{}", x, y))
+}
 
-    /// Kept for compatibility.
-    fn about_to_show(&self, _id: i32) -> bool {
-        false
+/// Filters the coordinates a tray passes to `Activate`.
+/// Some trays send `(0, 0)` or negative values when they have no real
+/// coordinates, so those are treated as "no position" instead of being
+/// mapped onto the top-left monitor.
+fn click_position(x: i32, y: i32) -> Option<(i32, i32)> {
+    if x < 0 || y < 0 || (x == 0 && y == 0) {
+        None
+    } else {
+        Some((x, y))
     }
+}
 
-    #[dbus_interface(property)]
-    fn version(&self) -> u32 {
-        3
+impl RestoreTarget {
+    /// Resolves the target to a concrete workspace id for the given window.
+    /// `click` is the position the tray icon was activated at, if any.
+    fn resolve(
+        self,
+        compositor: &dyn Compositor,
+        window_info: &WindowInfo,
+        click: Option<(i32, i32)>,
+    ) -> Result<i32> {
+        match self {
+            RestoreTarget::Current => Ok(hyprctl::<Workspace>(compositor, "activeworkspace")
+                .context("Failed to get active workspace.")?
+                .id),
+            RestoreTarget::Original => Ok(window_info.workspace.id),
+            RestoreTarget::MouseMonitor => {
+                let monitor = get_monitor_under_cursor(compositor)?;
+                info!(
+                    "Cursor is on monitor '{}', restoring to workspace {}",
+                    monitor.name, monitor.active_workspace.id
+                );
+                Ok(monitor.active_workspace.id)
+            }
+            RestoreTarget::ClickMonitor => {
+                let Some((x, y)) = click else {
+                    info!("Tray sent no click position, using the cursor's monitor");
+                    return RestoreTarget::MouseMonitor.resolve(compositor, window_info, None);
+                };
+                let monitor = get_monitor_at(compositor, x, y)?;
+                info!(
+                    "Clicked on monitor '{}', restoring to workspace {}",
+                    monitor.name, monitor.active_workspace.id
+                );
+                Ok(monitor.active_workspace.id)
+            }
+        }
     }
+}
 
-    #[dbus_interface(property)]
-    fn text_direction(&self) -> &str {
-        "ltr"
-    }
+// --- Special Workspace Handling ---
 
-    #[dbus_interface(property)]
-    fn status(&self) -> &str {
-        "normal"
-    }
+/// The special workspace minimized windows are parked on, set from `--special-workspace`.
+static SPECIAL_WORKSPACE: OnceLock<String> = OnceLock::new();
+
+fn special_workspace() -> &'static str {
+    SPECIAL_WORKSPACE
+        .get()
+        .map(String::as_str)
+        .unwrap_or("special:minimized")
 }
 
-// --- Status Notifier Item (Tray Icon) Implementation ---
+/// The display name `--rename-workspace` gives the special workspace while it
+/// holds `count` windows: its own name capitalized, e.g. `Minimized (3)`.
+fn special_workspace_label(count: u32) -> String {
+    let name = special_workspace().trim_start_matches("special:");
+    let mut chars = name.chars();
+    let capitalized: String = chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default();
+    format!("{} ({})", capitalized, count)
+}
 
-struct StatusNotifierItem {
-    window_info: WindowInfo,
-    exit_notify: Arc<Notify>,
+/// Finds the special workspace, whether it currently carries its own name or a count label.
+fn find_special_workspace(compositor: &dyn Compositor) -> Result<Option<WorkspaceSummary>> {
+    let workspaces: Vec<WorkspaceSummary> =
+        hyprctl(compositor, "workspaces").context("Failed to get workspace list from Hyprland.")?;
+    Ok(workspaces
+        .into_iter()
+        .find(|w| is_special_workspace(w.id, &w.name)))
 }
 
-#[dbus_interface(name = "org.kde.StatusNotifierItem")]
-impl StatusNotifierItem {
-    // --- Properties ---
-    #[dbus_interface(property)]
-    fn category(&self) -> &str {
-        "ApplicationStatus"
+/// Returns true for the special workspace, under its own name or its count label.
+/// Special workspaces of other instances, labeled or not, don't match.
+fn is_special_workspace(id: i32, name: &str) -> bool {
+    if name == special_workspace() {
+        return true;
     }
+    let Some(count) = name
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" ("))
+        .and_then(|(_, count)| count.parse::<u32>().ok())
+    else {
+        return false;
+    };
+    id < 0 && name == special_workspace_label(count)
+}
 
-    #[dbus_interface(property)]
-    fn id(&self) -> &str {
-        &self.window_info.class
-    }
+/// Returns true if the special workspace is shown on any monitor.
+fn special_workspace_shown(compositor: &dyn Compositor) -> Result<bool> {
+    let Some(special) = find_special_workspace(compositor)? else {
+        return Ok(false);
+    };
+    let monitors: Vec<Monitor> =
+        hyprctl(compositor, "monitors").context("Failed to get monitors.")?;
+    Ok(monitors
+        .iter()
+        .any(|m| m.special_workspace.id == special.id))
+}
 
-    #[dbus_interface(property)]
-    fn title(&self) -> &str {
-        &self.window_info.title
+/// Hides the special workspace if it is left open on screen without any windows,
+/// e.g. after restoring its only window while peeking into it.
+fn hide_empty_special_workspace(compositor: &dyn Compositor) -> Result<()> {
+    let Some(special) = find_special_workspace(compositor)? else {
+        // Hyprland already removed it.
+        return Ok(());
+    };
+    if special.windows > 0 {
+        return Ok(());
+    }
+    let monitors: Vec<Monitor> =
+        hyprctl(compositor, "monitors").context("Failed to get monitors.")?;
+    // togglespecialworkspace acts on the focused monitor only.
+    let shown = monitors
+        .iter()
+        .any(|m| m.focused && m.special_workspace.id == special.id);
+    if shown {
+        info!("Hiding the now empty special workspace.");
+        toggle_special_workspace(compositor, false)?;
     }
+    Ok(())
+}
 
-    #[dbus_interface(property)]
-    fn status(&self) -> &str {
-        "Active"
+/// Shows the special workspace on the focused monitor, or hides it if it is
+/// shown already. With `relabel`, the workspace gets its count label back afterwards.
+fn toggle_special_workspace(compositor: &dyn Compositor, relabel: bool) -> Result<()> {
+    // Toggling by name needs the workspace to carry its real name.
+    reset_special_workspace_name(compositor)?;
+    let name = special_workspace().trim_start_matches("special:");
+    hyprctl_dispatch(compositor, &format!("togglespecialworkspace {}", name))?;
+    if relabel {
+        update_special_workspace_label(compositor)?;
     }
+    Ok(())
+}
 
-    #[dbus_interface(property)]
-    fn icon_name(&self) -> &str {
-        &self.window_info.class
+/// Gives the special workspace back its real name, so that moving a window to
+/// it by name finds it again instead of creating a second one.
+fn reset_special_workspace_name(compositor: &dyn Compositor) -> Result<()> {
+    if let Some(workspace) = find_special_workspace(compositor)? {
+        if workspace.name != special_workspace() {
+            hyprctl_dispatch(
+                compositor,
+                &format!("renameworkspace {} {}", workspace.id, special_workspace()),
+            )?;
+        }
     }
+    Ok(())
+}
 
-    #[dbus_interface(property)]
-    fn tool_tip(&self) -> (String, Vec<(i32, i32, Vec<u8>)>, String, String) {
-        (
-            String::new(),
-            Vec::new(),
-            self.window_info.title.clone(),
-            String::new(),
-        )
+/// Renames the special workspace to show the current number of windows on it.
+/// Every minimizer process calls this after changing the workspace's contents,
+/// so the count stays correct without any coordination between them.
+fn update_special_workspace_label(compositor: &dyn Compositor) -> Result<()> {
+    if let Some(workspace) = find_special_workspace(compositor)? {
+        let label = special_workspace_label(workspace.windows);
+        if workspace.name != label {
+            hyprctl_dispatch(
+                compositor,
+                &format!("renameworkspace {} {}", workspace.id, label),
+            )?;
+        }
     }
+    Ok(())
+}
 
-    #[dbus_interface(property)]
-    fn item_is_menu(&self) -> bool {
-        false
+/// Moves a window to the special workspace without switching the user to it.
+/// Hyprland versions without `movetoworkspacesilent` get a plain `movetoworkspace`,
+/// after which the user is sent back to the workspace they were on.
+///
+/// Pinned windows are shown on every workspace and would not disappear, so they
+/// get unpinned first; `return_window` pins them again.
+fn move_to_special_workspace(compositor: &dyn Compositor, window_info: &WindowInfo) -> Result<()> {
+    if window_info.pinned {
+        // `pin` toggles, so this unpins the window.
+        hyprctl_dispatch(compositor, &format!("pin address:{}", window_info.address))?;
+    }
+    let silent = hyprctl_dispatch(
+        compositor,
+        &format!(
+            "movetoworkspacesilent {},address:{}",
+            special_workspace(),
+            window_info.address
+        ),
+    );
+    match silent {
+        Err(e)
+            if e.downcast_ref::<DispatchError>()
+                .is_some_and(DispatchError::is_invalid_dispatcher) =>
+        {
+            info!("'movetoworkspacesilent' is unavailable in this Hyprland version, falling back to 'movetoworkspace'.");
+            let prior: Workspace = hyprctl(compositor, "activeworkspace")
+                .context("Failed to get active workspace.")?;
+            hyprctl_dispatch(
+                compositor,
+                &format!(
+                    "movetoworkspace {},address:{}",
+                    special_workspace(),
+                    window_info.address
+                ),
+            )?;
+            hyprctl_dispatch(compositor, &format!("workspace {}", prior.id))
+        }
+        result => result,
     }
+}
+
+/// Addresses of the windows this process moved off the special workspace itself,
+/// so those moves are not mistaken for manual ones.
+static SELF_RESTORED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Whether this process restored the window itself, see [`SELF_RESTORED`].
+fn restored_by_us(address: &str) -> bool {
+    SELF_RESTORED.lock().unwrap().iter().any(|a| a == address)
+}
 
-    #[dbus_interface(property)]
-    fn menu(&self) -> ObjectPath<'_> {
-        ObjectPath::try_from("/Menu").unwrap()
+/// Returns how to name the workspace in a dispatch. The window's original
+/// workspace goes by its name if it has one: a named workspace that has been
+/// destroyed in the meantime only comes back with its name that way.
+fn workspace_selector(window_info: &WindowInfo, workspace_id: i32) -> String {
+    let name = &window_info.workspace.name;
+    if workspace_id != window_info.workspace.id || name.is_empty() || name.parse::<i32>().is_ok() {
+        return workspace_id.to_string();
     }
+    if workspace_id < 0 {
+        // Special workspaces are addressed as `special:NAME` already.
+        name.clone()
+    } else {
+        format!("name:{}", name)
+    }
+}
 
-    // --- Methods ---
-    fn activate(&self, _x: i32, _y: i32) {
-        println!("[D-Bus] Activate called (left-click)");
-        if let Ok(active_workspace) = hyprctl::<Workspace>("activeworkspace") {
-            if let Err(e) = hyprctl_dispatch(&format!(
-                "movetoworkspace {},address:{}",
-                active_workspace.id, self.window_info.address
-            ))
-            .and_then(|_| {
-                hyprctl_dispatch(&format!("focuswindow address:{}", self.window_info.address))
-            }) {
-                eprintln!("[Error] Failed to execute activate action: {}", e);
+/// Set once Hyprland rejected `movetoworkspacesilent` for a restore.
+static SILENT_MOVE_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Moves a window off the special workspace to the given workspace and
+/// reapplies the state it lost when it was minimized. A dispatch given as
+/// `then` runs in the same request as the move; returns false if Hyprland
+/// rejected it. With `silent`, the user is not taken along to the workspace.
+fn return_window(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+    workspace_id: i32,
+    then: Option<&str>,
+    silent: bool,
+) -> Result<bool> {
+    SELF_RESTORED
+        .lock()
+        .unwrap()
+        .push(window_info.address.clone());
+    let dispatcher = if silent && !SILENT_MOVE_UNSUPPORTED.load(Ordering::Relaxed) {
+        "movetoworkspacesilent"
+    } else {
+        "movetoworkspace"
+    };
+    let move_command = format!(
+        "{} {},address:{}",
+        dispatcher,
+        workspace_selector(window_info, workspace_id),
+        window_info.address
+    );
+    let mut then_done = true;
+    match then {
+        Some(then) => match hyprctl_dispatch_batch(compositor, &[&move_command, then]) {
+            Ok(()) => {}
+            Err(e)
+                if e.downcast_ref::<DispatchError>()
+                    .is_some_and(|e| e.command == then) =>
+            {
+                debug!("{}", e);
+                then_done = false;
             }
-        } else {
-            eprintln!("[Error] Failed to get active workspace");
+            Err(e) => return Err(e),
+        },
+        None => match hyprctl_dispatch(compositor, &move_command) {
+            Err(e)
+                if silent
+                    && e.downcast_ref::<DispatchError>()
+                        .is_some_and(DispatchError::is_invalid_dispatcher) =>
+            {
+                info!("'movetoworkspacesilent' is unavailable in this Hyprland version, falling back to 'movetoworkspace'.");
+                SILENT_MOVE_UNSUPPORTED.store(true, Ordering::Relaxed);
+                hyprctl_dispatch(
+                    compositor,
+                    &format!(
+                        "movetoworkspace {},address:{}",
+                        workspace_selector(window_info, workspace_id),
+                        window_info.address
+                    ),
+                )?
+            }
+            result => result?,
+        },
+    }
+    if window_info.pinned {
+        hyprctl_dispatch(compositor, &format!("pin address:{}", window_info.address))?;
+    }
+    if window_info.grouped.len() > 1 && !window_info.floating {
+        if let Err(e) = rejoin_group(compositor, window_info, workspace_id) {
+            warn!("Failed to move window back into its group: {}", e);
         }
-        self.exit_notify.notify_one();
     }
-
-    fn secondary_activate(&self, _x: i32, _y: i32) {
-        println!("[D-Bus] SecondaryActivate called (middle-click to close)");
-        if let Err(e) =
-            hyprctl_dispatch(&format!("closewindow address:{}", self.window_info.address))
-        {
-            eprintln!("[Error] Failed to execute secondary_activate action: {}", e);
+    if workspace_id == window_info.workspace.id {
+        if let Err(e) = return_to_original_monitor(compositor, window_info) {
+            warn!("Failed to move window back to its original monitor: {}", e);
+        }
+    }
+    if window_info.floating {
+        let same_workspace = workspace_id == window_info.workspace.id;
+        if let Err(e) = restore_floating(compositor, window_info, same_workspace) {
+            warn!("Failed to restore floating geometry: {}", e);
+        }
+    }
+    if window_info.fullscreen != 0 {
+        if let Err(e) = restore_fullscreen(compositor, window_info) {
+            warn!("Failed to restore fullscreen: {}", e);
         }
-        self.exit_notify.notify_one();
     }
+    metrics::RESTORED_TOTAL.inc();
+    Ok(then_done)
 }
 
-// --- Main Application Logic ---
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+/// Moves a restored window back into the group it was part of when it was
+/// minimized. `moveintogroup` only takes a direction, so it is worked out from
+/// where the group ended up relative to the window. Does nothing if no other
+/// member of the group is left on the workspace.
+fn rejoin_group(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+    workspace_id: i32,
+) -> Result<()> {
+    let clients = get_clients(compositor)?;
+    let Some(window) = clients.iter().find(|c| c.address == window_info.address) else {
+        return Ok(());
+    };
+    let Some(member) = clients.iter().find(|c| {
+        c.address != window_info.address
+            && c.workspace.id == workspace_id
+            && window_info.grouped.contains(&c.address)
+    }) else {
+        info!(
+            "The rest of the group of '{}' is gone, restoring it on its own.",
+            window_info.title
+        );
+        return Ok(());
+    };
 
-    // 1. Get window info based on CLI arguments
-    let mut window_info = if let Some(address) = args.window_address {
-        println!("Attempting to minimize window with address: {}", address);
-        get_window_by_address(&address)?
+    let center = |w: &WindowInfo| [w.at[0] + w.size[0] / 2, w.at[1] + w.size[1] / 2];
+    let [x, y] = center(window);
+    let [member_x, member_y] = center(member);
+    let (dx, dy) = (member_x - x, member_y - y);
+    let direction = if dx.abs() >= dy.abs() {
+        if dx < 0 {
+            "l"
+        } else {
+            "r"
+        }
+    } else if dy < 0 {
+        "u"
     } else {
-        println!("No window address provided, minimizing active window.");
-        hyprctl("activewindow").context("Failed to get active window. Is a window focused?")?
+        "d"
     };
 
-    println!(
-        "Minimizing window: '{}' ({}) from workspace {}",
-        window_info.title, window_info.class, window_info.workspace.id
-    );
+    // `moveintogroup` acts on the focused window.
+    hyprctl_dispatch(
+        compositor,
+        &format!("focuswindow address:{}", window_info.address),
+    )?;
+    hyprctl_dispatch(compositor, &format!("moveintogroup {}", direction))
+}
 
-    if window_info.class.is_empty() {
-        // Fallback to title if class is empty, for better icon matching
-        window_info.class = window_info.title.clone();
+/// Makes a restored window float again at its old size, in case workspace rules
+/// tiled it on the way back. The old position is only reused on the original
+/// workspace; elsewhere it could lie on another monitor entirely.
+fn restore_floating(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+    restore_position: bool,
+) -> Result<()> {
+    let address = &window_info.address;
+    let still_floating = get_window_by_address(compositor, address).is_ok_and(|w| w.floating);
+    if !still_floating {
+        hyprctl_dispatch(compositor, &format!("setfloating address:{}", address))?;
     }
+    let [width, height] = window_info.size;
+    hyprctl_dispatch(
+        compositor,
+        &format!(
+            "resizewindowpixel exact {} {},address:{}",
+            width, height, address
+        ),
+    )?;
+    if !restore_position {
+        return Ok(());
+    }
+    let [x, y] = window_info.at;
+    hyprctl_dispatch(
+        compositor,
+        &format!("movewindowpixel exact {} {},address:{}", x, y, address),
+    )
+}
 
-    // 2. Move the window to the special "minimized" workspace
-    hyprctl_dispatch(&format!(
-        "movetoworkspacesilent special:minimized,address:{}",
-        window_info.address
-    ))?;
-
-    // 3. Set up the D-Bus services
-    let exit_notify = Arc::new(Notify::new());
+/// Puts a window back into the fullscreen or maximized state it lost when it
+/// was moved to the special workspace.
+fn restore_fullscreen(compositor: &dyn Compositor, window_info: &WindowInfo) -> Result<()> {
+    let address = &window_info.address;
+    if get_window_by_address(compositor, address)
+        .is_ok_and(|w| w.fullscreen == window_info.fullscreen)
+    {
+        return Ok(());
+    }
+    for command in fullscreen_dispatches(window_info) {
+        hyprctl_dispatch(compositor, &command)?;
+    }
+    Ok(())
+}
 
-    let notifier_item = StatusNotifierItem {
-        window_info: window_info.clone(),
-        exit_notify: Arc::clone(&exit_notify),
+/// Returns the dispatches putting a window back into its fullscreen state:
+/// none for a normal window. The `fullscreen` dispatcher takes 0 for real
+/// fullscreen and 1 for maximized, and only acts on the focused window.
+fn fullscreen_dispatches(window_info: &WindowInfo) -> Vec<String> {
+    let mode = match window_info.fullscreen {
+        0 => return Vec::new(),
+        1 => 1,
+        _ => 0,
     };
+    vec![
+        format!("focuswindow address:{}", window_info.address),
+        format!("fullscreen {}", mode),
+    ]
+}
 
-    let dbus_menu = DbusMenu {
-        window_info: window_info.clone(),
-        exit_notify: Arc::clone(&exit_notify),
+/// Moves a window restored to its original workspace back to the monitor it
+/// was minimized from, in case the workspace has moved to another monitor since.
+/// Does nothing if the monitor is gone or nothing moved, as on single-monitor setups.
+fn return_to_original_monitor(compositor: &dyn Compositor, window_info: &WindowInfo) -> Result<()> {
+    let workspaces: Vec<WorkspaceSummary> =
+        hyprctl(compositor, "workspaces").context("Failed to get workspace list from Hyprland.")?;
+    let current = workspaces
+        .iter()
+        .find(|w| w.id == window_info.workspace.id)
+        .and_then(|w| w.monitor_id);
+    if current.is_none_or(|id| id == window_info.monitor) {
+        return Ok(());
+    }
+    let monitors: Vec<Monitor> =
+        hyprctl(compositor, "monitors").context("Failed to get monitor list from Hyprland.")?;
+    let Some(original) = monitors.iter().find(|m| m.id == window_info.monitor) else {
+        info!("The window's original monitor is gone, leaving it where it is.");
+        return Ok(());
+    };
+    info!(
+        "Workspace {} moved to another monitor, returning the window to '{}'",
+        window_info.workspace.id, original.name
+    );
+    // `movewindow` only acts on the focused window.
+    hyprctl_dispatch(
+        compositor,
+        &format!("focuswindow address:{}", window_info.address),
+    )?;
+    hyprctl_dispatch(compositor, &format!("movewindow mon:{}", original.name))
+}
+
+/// How long to wait before focusing a restored XWayland window.
+const XWAYLAND_FOCUS_DELAY: Duration = Duration::from_millis(50);
+
+/// Moves a window to the given workspace and focuses it.
+fn move_and_focus(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+    workspace_id: i32,
+) -> Result<()> {
+    if window_info.xwayland {
+        return_window(compositor, window_info, workspace_id, None, false)?;
+        // XWayland windows can take a moment to be mapped again after the move;
+        // focusing right away sometimes leaves focus on the previous window.
+        std::thread::sleep(XWAYLAND_FOCUS_DELAY);
+        return focus_window(compositor, window_info);
+    }
+    // Moving and focusing in one request keeps the window from showing up unfocused first.
+    let focus = format!("focuswindow address:{}", window_info.address);
+    if !return_window(compositor, window_info, workspace_id, Some(&focus), false)? {
+        focus_window(compositor, window_info)?;
+    }
+    Ok(())
+}
+
+/// Focuses a window by address. Apps that recreate their window while minimized
+/// invalidate the address, so fall back to the pid, and then to any window of the class.
+/// The fallbacks skip windows on the special workspace, which belong to other minimizers.
+fn focus_window(compositor: &dyn Compositor, window_info: &WindowInfo) -> Result<()> {
+    let address_error = match hyprctl_dispatch(
+        compositor,
+        &format!("focuswindow address:{}", window_info.address),
+    ) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
     };
+    warn!(
+        "[Focus] Focusing by address failed ({}), trying pid {} and class '{}'.",
+        address_error, window_info.pid, window_info.class
+    );
+
+    let clients: Vec<WindowInfo> = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| !is_special_workspace(c.workspace.id, &c.workspace.name))
+        .collect();
+    // `focuswindow pid:` could pick a sibling window that is still minimized.
+    let (replacement, by) = match clients
+        .iter()
+        .find(|c| window_info.pid > 0 && c.pid == window_info.pid)
+    {
+        Some(c) => (c, format!("pid {}", window_info.pid)),
+        None => {
+            let c = clients
+                .iter()
+                .find(|c| c.class == window_info.class)
+                .ok_or(address_error)?;
+            (c, format!("class '{}'", window_info.class))
+        }
+    };
+    hyprctl_dispatch(
+        compositor,
+        &format!("focuswindow address:{}", replacement.address),
+    )?;
+    info!("[Focus] Focused window {} by {}.", replacement.address, by);
+    Ok(())
+}
+
+/// Restores a window to the given workspace, applying the restore options from `args`.
+fn restore_window(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+    workspace_id: i32,
+    args: &Args,
+) -> Result<()> {
+    if args.silent_restore {
+        let previous = hyprctl::<WindowInfo>(compositor, "activewindow").ok();
+        return_window(compositor, window_info, workspace_id, None, true)?;
+        // Restoring floating, fullscreen or group state needs the window focused
+        // for a moment; hand focus back afterwards.
+        if let Some(previous) = previous.filter(|p| p.address != window_info.address) {
+            if let Err(e) = hyprctl_dispatch(
+                compositor,
+                &format!("focuswindow address:{}", previous.address),
+            ) {
+                warn!("Failed to focus the previously focused window again: {}", e);
+            }
+        }
+    } else if args.restore_focus_previous {
+        // Remember what the user was focused on before the window shows up.
+        let previous = hyprctl::<WindowInfo>(compositor, "activewindow").ok();
+        let refocus = previous
+            .filter(|p| p.address != window_info.address)
+            .map(|p| format!("focuswindow address:{}", p.address));
+        if !return_window(
+            compositor,
+            window_info,
+            workspace_id,
+            refocus.as_deref(),
+            false,
+        )? {
+            warn!("Failed to focus the previously focused window again.");
+        }
+    } else {
+        move_and_focus(compositor, window_info, workspace_id)?;
+    }
+    if args.solo_on_restore {
+        minimize_others_on_workspace(compositor, workspace_id, &window_info.address)?;
+    }
+    Ok(())
+}
 
-    let bus_name = format!(
-        "org.kde.StatusNotifierItem.minimizer.p{}",
-        std::process::id()
+/// Restores each window to its original workspace, carrying on past failures.
+/// Returns how many of the windows were restored.
+fn restore_all(compositor: &dyn Compositor, windows: &[WindowInfo]) -> usize {
+    windows
+        .iter()
+        .filter(|w| match move_and_focus(compositor, w, w.workspace.id) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to restore window '{}': {}", w.title, e);
+                false
+            }
+        })
+        .count()
+}
+
+/// Minimizes every window on the workspace except the one with the given address.
+/// Each window is handed to a new minimizer process so it gets its own tray icon.
+/// Returns the addresses of the windows that were handed off.
+fn minimize_others_on_workspace(
+    compositor: &dyn Compositor,
+    workspace_id: i32,
+    keep_address: &str,
+) -> Result<Vec<String>> {
+    let exe = std::env::current_exe().context("Failed to locate the minimizer executable.")?;
+    let others: Vec<WindowInfo> = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| c.workspace.id == workspace_id && c.address != keep_address)
+        .collect();
+    info!(
+        "Minimizing {} other window(s) on workspace {}",
+        others.len(),
+        workspace_id
     );
+    let mut minimized = Vec::new();
+    for other in others {
+        let mut command = Command::new(&exe);
+        command.arg(&other.address);
+        if DRY_RUN.load(Ordering::Relaxed) {
+            command.arg("--dry-run");
+        }
+        command.arg("--backend").arg(ipc::backend().name());
+        // Keeps the helpers on this instance even if it was picked with --instance.
+        if let Ok(signature) = ipc::signature() {
+            command.env("HYPRLAND_INSTANCE_SIGNATURE", signature);
+        }
+        match command.spawn() {
+            Ok(_) => minimized.push(other.address),
+            Err(e) => error!(
+                "Failed to minimize window '{}' ({}): {}",
+                other.title, other.address, e
+            ),
+        }
+    }
+    Ok(minimized)
+}
 
-    let connection = ConnectionBuilder::session()?
-        .name(bus_name.as_str())?
-        .serve_at("/StatusNotifierItem", notifier_item)?
-        .serve_at("/Menu", dbus_menu)?
-        .build()
-        .await?;
+// --- Subcommands ---
 
-    // Create an Arc of the connection to share with the watcher task.
-    let arc_conn = Arc::new(connection);
+/// Minimizes everything but the focused window, or with `off`, restores what
+/// an earlier call minimized.
+fn focus_mode(compositor: &dyn Compositor, off: bool) -> Result<()> {
+    let mut hidden = state::FocusModeWindows::load()?;
+    if off {
+        if hidden.addresses.is_empty() {
+            println!("Focus mode is not active.");
+            return Ok(());
+        }
+        restore_matching(compositor, &hidden.addresses, None)?;
+        hidden.addresses.clear();
+        return hidden.save();
+    }
 
-    println!("D-Bus service '{}' is running.", bus_name);
+    let focused: WindowInfo = hyprctl(compositor, "activewindow")
+        .context("Failed to get active window from Hyprland.")?;
+    if focused.address.is_empty() {
+        anyhow::bail!("No window is focused.");
+    }
+    let minimized =
+        minimize_others_on_workspace(compositor, focused.workspace.id, &focused.address)?;
+    println!(
+        "Focus mode on: minimized {} window(s), keeping '{}'.",
+        minimized.len(),
+        focused.title
+    );
+    // Keep windows from an earlier, still active focus mode restorable as well.
+    for address in minimized {
+        if !hidden.addresses.contains(&address) {
+            hidden.addresses.push(address);
+        }
+    }
+    hidden.save()
+}
 
-    // 4. Initial registration with the StatusNotifierWatcher
-    let initial_registration_result = async {
-        let watcher_proxy: Proxy<'_> = zbus::ProxyBuilder::new_bare(&*arc_conn)
-            .interface("org.kde.StatusNotifierWatcher")?
-            .path("/StatusNotifierWatcher")?
-            .destination("org.kde.StatusNotifierWatcher")?
-            .build()
-            .await?;
-        watcher_proxy
-            .call_method("RegisterStatusNotifierItem", &(bus_name.as_str(),))
-            .await?;
-        Ok::<(), anyhow::Error>(())
+/// Restores every window on the special workspace that matches one of the
+/// given addresses or the class to the current workspace.
+fn restore_matching(
+    compositor: &dyn Compositor,
+    addresses: &[String],
+    class: Option<&str>,
+) -> Result<()> {
+    if addresses.is_empty() && class.is_none() {
+        anyhow::bail!("No selector given. Pass window addresses or --class.");
+    }
+    let special = match find_special_workspace(compositor)? {
+        Some(special) => special,
+        None => {
+            println!("No windows are minimized.");
+            return Ok(());
+        }
+    };
+    let matching: Vec<WindowInfo> = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| c.workspace.id == special.id)
+        .filter(|c| addresses.contains(&c.address) || class == Some(c.class.as_str()))
+        .collect();
+
+    let active_workspace: Workspace =
+        hyprctl(compositor, "activeworkspace").context("Failed to get active workspace.")?;
+    let mut restored = 0;
+    for window_info in &matching {
+        match move_and_focus(compositor, window_info, active_workspace.id) {
+            Ok(()) => restored += 1,
+            Err(e) => error!("Failed to restore window '{}': {}", window_info.title, e),
+        }
+    }
+    println!(
+        "Restored {} of {} matching window(s).",
+        restored,
+        matching.len()
+    );
+    Ok(())
+}
+
+/// A minimized window as printed by the `list` subcommand.
+#[derive(Serialize)]
+struct ListedWindow<'a> {
+    address: &'a str,
+    title: &'a str,
+    class: &'a str,
+}
+
+/// Prints the windows on the special workspace as JSON.
+fn list_minimized(compositor: &dyn Compositor) -> Result<()> {
+    let windows: Vec<WindowInfo> = match find_special_workspace(compositor)? {
+        Some(special) => get_clients(compositor)?
+            .into_iter()
+            .filter(|c| c.workspace.id == special.id)
+            .collect(),
+        None => Vec::new(),
+    };
+    let listed: Vec<ListedWindow> = windows
+        .iter()
+        .map(|w| ListedWindow {
+            address: &w.address,
+            title: &w.title,
+            class: &w.class,
+        })
+        .collect();
+    println!("{}", serde_json::to_string(&listed)?);
+    Ok(())
+}
+
+// --- Main Application Logic ---
+
+/// Picks the window to minimize according to the selector options.
+fn select_window(compositor: &dyn Compositor, args: &Args) -> Result<WindowInfo> {
+    if let Some(index) = args.match_focused_history {
+        info!(
+            "Attempting to minimize window {} in the focus history",
+            index
+        );
+        return get_window_by_focus_history(compositor, index);
+    }
+    if let Some(pattern) = &args.class {
+        info!(
+            "Attempting to minimize a window with class matching: {}",
+            pattern
+        );
+        return get_window_by_class(compositor, pattern);
+    }
+    if let Some(pattern) = &args.title {
+        info!(
+            "Attempting to minimize a window with title matching: {}",
+            pattern
+        );
+        return get_window_by_title(compositor, pattern);
+    }
+    if let (Some(pid), None) = (args.pid, &args.window_address) {
+        info!("Attempting to minimize a window of pid: {}", pid);
+        return get_window_by_pid(compositor, pid);
+    }
+    if let Some(address) = &args.window_address {
+        info!("Attempting to minimize window with address: {}", address);
+        get_window_by_address(compositor, address)
+    } else {
+        info!("No window address provided, minimizing active window.");
+        let active: WindowInfo = hyprctl(compositor, "activewindow")
+            .context("Failed to get active window. Is a window focused?")?;
+        if is_own_window(&active) {
+            anyhow::bail!(
+                "The active window belongs to the minimizer itself; refusing to minimize it."
+            );
+        }
+        // When invoked from a script while the user looks at another workspace,
+        // "the active window" may not be the one they expect.
+        let active_workspace: Workspace =
+            hyprctl(compositor, "activeworkspace").context("Failed to get active workspace.")?;
+        if active.workspace.id != active_workspace.id && !args.force {
+            anyhow::bail!(
+                "The active window '{}' is on workspace {}, but workspace {} is being viewed. Pass --force to minimize it anyway.",
+                active.title,
+                active.workspace.id,
+                active_workspace.id
+            );
+        }
+        Ok(active)
+    }
+}
+
+/// Looks up the minimized window. Returns `None` once it was restored or closed
+/// behind our back and the minimizer should exit.
+fn minimized_window(compositor: &dyn Compositor, address: &str) -> Option<WindowInfo> {
+    match hyprctl::<Vec<WindowInfo>>(compositor, "clients") {
+        Ok(clients) => match clients.into_iter().find(|c| c.address == address) {
+            Some(client) if is_special_workspace(client.workspace.id, &client.workspace.name) => {
+                Some(client)
+            }
+            // Any other workspace, including other special ones, means it was moved away.
+            Some(client) => {
+                info!(
+                    "Window restored externally to workspace '{}'. Exiting.",
+                    client.workspace.name
+                );
+                None
+            }
+            None => {
+                info!("Window closed externally. Exiting.");
+                None
+            }
+        },
+        Err(e) => {
+            error!("Error checking window state: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the window's title, or while it has none (e.g. right after mapping)
+/// its initial title, the application name from its desktop entry or its class.
+fn display_title(window_info: &WindowInfo) -> String {
+    if !window_info.title.trim().is_empty() {
+        return window_info.title.clone();
+    }
+    if !window_info.initial_title.trim().is_empty() {
+        return window_info.initial_title.clone();
+    }
+    if window_info.class.is_empty() {
+        return "Untitled window".to_string();
+    }
+    icon::app_name_for_class(&window_info.class).unwrap_or_else(|| window_info.class.clone())
+}
+
+/// Returns the pixmap to serve for a window of `class`: the `--icon` file if
+/// given, else the class's icon from its desktop entry. Empty if neither loads.
+fn icon_pixmap(args: &Args, class: &str) -> Vec<icon::Pixmap> {
+    let icon = match &args.icon {
+        Some(path) => icon::load(path).map(Some),
+        None => icon::for_class(class),
+    };
+    match icon {
+        Ok(icon) => icon.into_iter().collect(),
+        Err(e) => {
+            warn!("Failed to load icon for '{}': {:#}", class, e);
+            Vec::new()
+        }
+    }
+}
+
+/// How often and how long to look for a recreated window under `--track-by identity`.
+const ADOPT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const ADOPT_ATTEMPTS: u32 = 10;
+
+/// Looks for the window under a new address, going by pid and initial class, after
+/// its address went away. Points the tray icon at the new address and returns
+/// true if one turned up.
+async fn adopt_new_address(
+    conn: &dbus::Connection,
+    compositor: &dyn Compositor,
+    paths: &dbus::ItemPaths,
+    known: &mut WindowInfo,
+) -> bool {
+    if known.pid <= 0 || known.initial_class.is_empty() {
+        return false;
     }
+    // The replacement window may be mapped only after the old one is gone.
+    for _ in 0..ADOPT_ATTEMPTS {
+        let successor = get_clients(compositor).ok().and_then(|clients| {
+            clients.into_iter().find(|c| {
+                c.address != known.address
+                    && c.pid == known.pid
+                    && c.initial_class == known.initial_class
+                    && is_special_workspace(c.workspace.id, &c.workspace.name)
+            })
+        });
+        if let Some(successor) = successor {
+            info!(
+                "[Monitor] Window moved from {} to {}, following it.",
+                known.address, successor.address
+            );
+            if let Err(e) = dbus::update_address(conn, paths, &successor.address).await {
+                error!("Failed to update the tray icon's window: {}", e);
+            }
+            follow_on_panic(&known.address, &successor.address);
+            known.address = successor.address;
+            return true;
+        }
+        tokio::time::sleep(ADOPT_RETRY_INTERVAL).await;
+    }
+    false
+}
+
+/// Re-reads the minimized window and passes a changed title or class on to its
+/// tray icon. Returns false when the minimizer should exit.
+async fn refresh_window(
+    conn: &dbus::Connection,
+    compositor: &dyn Compositor,
+    paths: &dbus::ItemPaths,
+    args: &Args,
+    known: &mut WindowInfo,
+) -> bool {
+    if args.track_by == TrackBy::Identity
+        && get_window_by_address(compositor, &known.address).is_err()
+        && !adopt_new_address(conn, compositor, paths, known).await
+    {
+        info!("Window closed externally. Exiting.");
+        return false;
+    }
+    let Some(client) = minimized_window(compositor, &known.address) else {
+        return false;
+    };
+    let title = display_title(&client);
+    if title != known.title {
+        debug!("[Monitor] Window title changed to '{}'", title);
+        if let Err(e) = dbus::update_title(conn, paths, &title).await {
+            error!("Failed to update the tray icon's title: {}", e);
+        }
+        known.title = title;
+    }
+    // Windows that had no class at first were given their title as the class.
+    // The icon follows the initial class where there is one, which does not change.
+    if known.initial_class.is_empty() && !client.class.is_empty() && client.class != known.class {
+        debug!("[Monitor] Window class changed to '{}'", client.class);
+        let pixmap = icon_pixmap(args, &client.class);
+        if let Err(e) = dbus::update_class(conn, paths, &client.class, pixmap).await {
+            error!("Failed to update the tray icon's icon: {}", e);
+        }
+        known.class = client.class;
+    }
+    true
+}
+
+/// Follows the minimized window: keeps its tray icon's title and icon current and fires
+/// `exit_notify` once the window is restored or closed by anything other than
+/// the tray icon. Uses the event socket and falls back to polling
+/// `hyprctl clients` every `--poll-interval` when the socket is unavailable.
+async fn watch_window_state(
+    conn: Arc<dbus::Connection>,
+    compositor: Arc<dyn Compositor>,
+    paths: dbus::ItemPaths,
+    mut window_info: WindowInfo,
+    args: Arc<Args>,
+    exit_notify: Arc<Notify>,
+) {
+    let compositor = &*compositor;
+    let poll_interval = Duration::from_millis(args.poll_interval);
+    match events::EventStream::connect().await {
+        Ok(mut events) => {
+            // The window may have changed before we started listening.
+            if !refresh_window(&conn, compositor, &paths, &args, &mut window_info).await {
+                exit_notify.notify_one();
+                return;
+            }
+            loop {
+                let event = match events.next().await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => {
+                        warn!("[Monitor] Event socket closed, falling back to polling.");
+                        break;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "[Monitor] Event socket failed ({}), falling back to polling.",
+                            e
+                        );
+                        break;
+                    }
+                };
+                if event.address().as_deref() != Some(window_info.address.as_str()) {
+                    continue;
+                }
+                match event.name.as_str() {
+                    "closewindow"
+                        if args.track_by == TrackBy::Identity
+                            && adopt_new_address(&conn, compositor, &paths, &mut window_info)
+                                .await => {}
+                    "closewindow" => {
+                        info!("Window closed externally. Exiting.");
+                        exit_notify.notify_one();
+                        return;
+                    }
+                    "movewindow" | "windowtitle" | "windowtitlev2"
+                        if !refresh_window(&conn, compositor, &paths, &args, &mut window_info)
+                            .await =>
+                    {
+                        exit_notify.notify_one();
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Err(e) => warn!(
+            "[Monitor] Event socket unavailable ({}), falling back to polling.",
+            e
+        ),
+    }
+
+    let mut interval = interval(poll_interval);
+    loop {
+        interval.tick().await;
+        if !refresh_window(&conn, compositor, &paths, &args, &mut window_info).await {
+            exit_notify.notify_one();
+            return;
+        }
+    }
+}
+
+/// Runs `cycles` minimize/restore round trips on the window and prints the
+/// average latency of each half.
+fn soak(compositor: &dyn Compositor, window_info: &WindowInfo, cycles: u32) -> Result<()> {
+    let mut minimize_time = Duration::ZERO;
+    let mut restore_time = Duration::ZERO;
+    for cycle in 1..=cycles {
+        let started = Instant::now();
+        move_to_special_workspace(compositor, window_info)
+            .with_context(|| format!("Minimizing failed in cycle {}", cycle))?;
+        minimize_time += started.elapsed();
+
+        let started = Instant::now();
+        return_window(
+            compositor,
+            window_info,
+            window_info.workspace.id,
+            None,
+            false,
+        )
+        .with_context(|| format!("Restoring failed in cycle {}", cycle))?;
+        restore_time += started.elapsed();
+    }
+    if cycles > 0 {
+        println!(
+            "{} cycle(s): avg minimize {:?}, avg restore {:?}, {} hyprctl spawn(s)",
+            cycles,
+            minimize_time / cycles,
+            restore_time / cycles,
+            metrics::SUBPROCESS_SPAWNS_TOTAL.get()
+        );
+    }
+    Ok(())
+}
+
+/// Moves the window to the special workspace and updates the counters and label.
+fn minimize(compositor: &dyn Compositor, window_info: &WindowInfo, args: &Args) -> Result<()> {
+    if args.rename_workspace {
+        if let Err(e) = reset_special_workspace_name(compositor) {
+            error!("Failed to reset special workspace name: {}", e);
+        }
+    }
+    // Decide where focus goes before the window is gone from the focus history.
+    let next_focus = if window_info.focus_history_id == 0 && !args.no_refocus {
+        next_in_focus_history(compositor, window_info)
+    } else {
+        None
+    };
+    move_to_special_workspace(compositor, window_info)?;
+    if let Some(next) = next_focus {
+        debug!("Focusing '{}' after minimizing", next.title);
+        if let Err(e) =
+            hyprctl_dispatch(compositor, &format!("focuswindow address:{}", next.address))
+        {
+            warn!("Failed to focus the previous window: {}", e);
+        }
+    }
+    metrics::MINIMIZED_TOTAL.inc();
+    metrics::ITEMS_MINIMIZED.inc();
+    if args.rename_workspace {
+        if let Err(e) = update_special_workspace_label(compositor) {
+            error!("Failed to rename special workspace: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the most recently focused other window on the window's workspace.
+fn next_in_focus_history(
+    compositor: &dyn Compositor,
+    window_info: &WindowInfo,
+) -> Option<WindowInfo> {
+    let clients = match get_clients(compositor) {
+        Ok(clients) => clients,
+        Err(e) => {
+            warn!("Failed to look up the focus history: {}", e);
+            return None;
+        }
+    };
+    clients
+        .into_iter()
+        .filter(|c| c.address != window_info.address && c.workspace.id == window_info.workspace.id)
+        .filter(|c| c.focus_history_id >= 0)
+        .min_by_key(|c| c.focus_history_id)
+}
+
+/// Returns the window as its tray item knows it now. Tasks read it from there
+/// rather than keep their own copy: with `--track-by identity` the address
+/// changes when the window gets recreated.
+async fn current_window(
+    conn: &dbus::Connection,
+    paths: &dbus::ItemPaths,
+    fallback: &WindowInfo,
+) -> WindowInfo {
+    dbus::item_window(conn, paths)
+        .await
+        .unwrap_or_else(|_| fallback.clone())
+}
+
+/// Serves the tray icon of a minimized window on `arc_conn` and waits until the
+/// window is restored, closed or the process is interrupted. `service` is what
+/// gets registered with the watcher: the bus name, or the item path when
+/// several items share the connection.
+async fn run_item(
+    arc_conn: Arc<dbus::Connection>,
+    compositor: Arc<dyn Compositor>,
+    mut window_info: WindowInfo,
+    args: Arc<Args>,
+    paths: dbus::ItemPaths,
+    service: String,
+) -> Result<()> {
+    if window_info.class.is_empty() {
+        // Fallback to the initial class, then the title, for better icon matching
+        window_info.class = if window_info.initial_class.is_empty() {
+            window_info.title.clone()
+        } else {
+            window_info.initial_class.clone()
+        };
+    }
+    window_info.title = display_title(&window_info);
+
+    // 3. Set up the D-Bus services
+    let exit_notify = Arc::new(Notify::new());
+
+    let quirks = args.tray_quirks();
+    debug!("Using tray quirks: {:?}", quirks);
+
+    let learned_target = if args.learn_restore_target {
+        match state::LearnedTargets::load() {
+            Ok(targets) => targets.get(&window_info.class),
+            Err(e) => {
+                error!("Failed to load learned restore targets: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let auto_close_at = args
+        .auto_close_after
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+
+    let status = Arc::new(Mutex::new(ItemStatus::Active));
+    let close_request = Arc::new(Notify::new());
+
+    let icon_pixmap = icon_pixmap(&args, window_info.icon_class());
+    let monitor_name = dbus::monitor_name(&*compositor, window_info.monitor);
+
+    let notifier_item = StatusNotifierItem {
+        window_info: window_info.clone(),
+        args: Arc::clone(&args),
+        compositor: Arc::clone(&compositor),
+        quirks: quirks.clone(),
+        learned_target,
+        auto_close_at,
+        status: Arc::clone(&status),
+        icon_pixmap,
+        monitor_name,
+        menu_path: paths.menu.clone(),
+        exit_notify: Arc::clone(&exit_notify),
+        close_request: Arc::clone(&close_request),
+        activation_token: Arc::default(),
+        runtime: Some(tokio::runtime::Handle::current()),
+    };
+
+    let dbus_menu = DbusMenu::new(
+        window_info.clone(),
+        Arc::clone(&args),
+        Arc::clone(&compositor),
+        Arc::clone(&exit_notify),
+        Arc::clone(&close_request),
+    );
+
+    dbus::add_item(&arc_conn, &paths, notifier_item, dbus_menu).await?;
+    debug!("Serving tray item at {}", paths.item);
+
+    // 4. Initial registration with the StatusNotifierWatcher
+    let initial_registration_result = dbus::register_when_available(
+        &arc_conn,
+        &quirks.watcher_names,
+        &service,
+        Duration::from_millis(args.dbus_timeout),
+        Duration::from_millis(args.watcher_wait),
+    )
     .await;
 
     if let Err(e) = initial_registration_result {
-        eprintln!("Could not register with StatusNotifierWatcher: {}", e);
-        eprintln!("Is a tray like Waybar running?");
-        let _ = hyprctl_dispatch(&format!(
-            "movetoworkspace {},address:{}",
-            window_info.workspace.id, window_info.address
-        ));
+        error!("Could not register with StatusNotifierWatcher: {}", e);
+        error!("Is a tray like Waybar running?");
+        let _ = dbus::remove_item(&arc_conn, &paths).await;
+        let _ = return_window(
+            &*compositor,
+            &window_info,
+            window_info.workspace.id,
+            None,
+            false,
+        );
+        metrics::ITEMS_MINIMIZED.dec();
+        if args.rename_workspace {
+            let _ = update_special_workspace_label(&*compositor);
+        }
         anyhow::bail!("Failed to register tray icon.");
     }
-    println!("Registration successful.");
+    info!("Registration successful.");
+
+    // Background tasks of this item, stopped once it goes away.
+    let mut tasks = Vec::new();
+
+    // Confirm the minimize, in case the icon lands in a collapsed tray.
+    if !args.no_notify {
+        let notify_conn = Arc::clone(&arc_conn);
+        let icon = window_info.icon_class().to_string();
+        let summary = format!("Minimized {} to tray", window_info.title);
+        let timeout = Duration::from_millis(args.dbus_timeout);
+        tokio::spawn(async move {
+            if let Err(e) = dbus::notify(&notify_conn, &icon, &summary, &[], timeout).await {
+                warn!("Failed to show notification: {}", e);
+            }
+        });
+    }
 
-    // NEW: Task to watch for Waybar restarts and re-register the icon.
-    let conn_clone_watcher = Arc::clone(&arc_conn);
-    let bus_name_clone = bus_name.clone();
-    tokio::spawn(async move {
-        let dbus_proxy = match zbus::fdo::DBusProxy::new(&*conn_clone_watcher).await {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("[Watcher] Failed to connect to D-Bus proxy: {}", e);
-                return;
+    // Watch for Waybar restarts and re-register the icon.
+    tasks.push(tokio::spawn(dbus::watch_for_watcher_restarts(
+        Arc::clone(&arc_conn),
+        quirks.clone(),
+        service.clone(),
+        Duration::from_millis(args.dbus_timeout),
+    )));
+
+    // Ask before closing the window from the tray icon.
+    if args.confirm_close {
+        let confirm_conn = Arc::clone(&arc_conn);
+        let confirm_paths = paths.clone();
+        let confirm_window_info = window_info.clone();
+        let confirm_exit_notify = Arc::clone(&exit_notify);
+        let confirm_compositor = Arc::clone(&compositor);
+        let timeout = Duration::from_millis(args.dbus_timeout);
+        tasks.push(tokio::spawn(async move {
+            let compositor = &*confirm_compositor;
+            let question = format!("Close {}?", confirm_window_info.title);
+            loop {
+                close_request.notified().await;
+                let confirmed = dbus::confirm_close(
+                    &confirm_conn,
+                    confirm_window_info.icon_class(),
+                    &question,
+                    timeout,
+                )
+                .await;
+                match confirmed {
+                    Ok(true) => {
+                        info!("Close confirmed. Closing window.");
+                        let window_info =
+                            current_window(&confirm_conn, &confirm_paths, &confirm_window_info)
+                                .await;
+                        if let Err(e) = hyprctl_dispatch(
+                            compositor,
+                            &format!("closewindow address:{}", window_info.address),
+                        ) {
+                            error!("Failed to close window: {}", e);
+                        }
+                        confirm_exit_notify.notify_one();
+                        break;
+                    }
+                    Ok(false) => info!("Close cancelled."),
+                    Err(e) => error!("Failed to ask for confirmation: {}", e),
+                }
             }
-        };
+        }));
+    }
 
-        let mut owner_changes = match dbus_proxy.receive_name_owner_changed().await {
-            Ok(s) => s,
+    // Urgent windows, e.g. a ringing call, are restored with --restore-on-urgent and
+    // flagged on the tray icon otherwise.
+    let urgent_conn = Arc::clone(&arc_conn);
+    let urgent_paths = paths.clone();
+    let urgent_status = Arc::clone(&status);
+    let urgent_window_info = window_info.clone();
+    let urgent_args = Arc::clone(&args);
+    let urgent_exit_notify = Arc::clone(&exit_notify);
+    let urgent_compositor = Arc::clone(&compositor);
+    tasks.push(tokio::spawn(async move {
+        let compositor = &*urgent_compositor;
+        let mut events = match events::EventStream::connect().await {
+            Ok(events) => events,
             Err(e) => {
-                eprintln!("[Watcher] Failed to listen for owner changes: {}", e);
+                error!("[Urgent] Failed to listen for urgent windows: {}", e);
                 return;
             }
         };
-
-        println!("[Watcher] Watching for 'org.kde.StatusNotifierWatcher' restarts...");
-
-        while let Some(signal) = owner_changes.next().await {
-            if let Ok(args) = signal.args() {
-                if args.name() == "org.kde.StatusNotifierWatcher" && args.new_owner().is_some() {
-                    println!("[Watcher] Tray service detected. Re-registering icon.");
-                    let re_register_result = async {
-                        // Give the watcher a moment to get ready
-                        tokio::time::sleep(Duration::from_millis(100)).await;
-                        let watcher_proxy: Proxy<'_> =
-                            zbus::ProxyBuilder::new_bare(&*conn_clone_watcher)
-                                .interface("org.kde.StatusNotifierWatcher")?
-                                .path("/StatusNotifierWatcher")?
-                                .destination("org.kde.StatusNotifierWatcher")?
-                                .build()
-                                .await?;
-                        watcher_proxy
-                            .call_method("RegisterStatusNotifierItem", &(bus_name_clone.as_str(),))
-                            .await?;
-                        Ok::<(), anyhow::Error>(())
+        loop {
+            let event = match events.next().await {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    warn!("[Urgent] Event socket closed.");
+                    break;
+                }
+                Err(e) => {
+                    error!("[Urgent] Failed to read from event socket: {}", e);
+                    break;
+                }
+            };
+            let window_info =
+                current_window(&urgent_conn, &urgent_paths, &urgent_window_info).await;
+            let ours = event.address().as_deref() == Some(&window_info.address);
+            match event.name.as_str() {
+                "urgent" if ours && urgent_args.restore_on_urgent => {
+                    info!("[Urgent] Window demands attention. Restoring.");
+                    if let Err(e) = RestoreTarget::Current
+                        .resolve(compositor, &window_info, None)
+                        .and_then(|id| restore_window(compositor, &window_info, id, &urgent_args))
+                    {
+                        error!("Failed to restore urgent window: {}", e);
                     }
+                    urgent_exit_notify.notify_one();
+                    break;
+                }
+                "urgent" if ours => {
+                    info!("[Urgent] Window demands attention.");
+                    let result = dbus::set_status(
+                        &urgent_conn,
+                        &urgent_paths.item,
+                        &urgent_status,
+                        ItemStatus::NeedsAttention,
+                    )
                     .await;
-
-                    if let Err(e) = re_register_result {
-                        eprintln!("[Watcher] Failed to re-register icon: {}", e);
+                    if let Err(e) = result {
+                        error!("Failed to set attention status: {}", e);
                     }
                 }
+                // Hyprland clears the urgent flag once the window gets focused,
+                // e.g. from inside the special workspace.
+                "activewindowv2"
+                    if ours && *urgent_status.lock().unwrap() == ItemStatus::NeedsAttention =>
+                {
+                    let result = dbus::set_status(
+                        &urgent_conn,
+                        &urgent_paths.item,
+                        &urgent_status,
+                        ItemStatus::Active,
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        error!("Failed to set active status: {}", e);
+                    }
+                }
+                _ => {}
             }
         }
-    });
+    }));
+
+    // Learn where the user moves the window by hand.
+    if args.learn_restore_target {
+        let learn_conn = Arc::clone(&arc_conn);
+        let learn_paths = paths.clone();
+        let learn_window_info = window_info.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut events = match events::EventStream::connect().await {
+                Ok(events) => events,
+                Err(e) => {
+                    error!("[Learn] Failed to listen for window moves: {}", e);
+                    return;
+                }
+            };
+            while let Ok(Some(event)) = events.next().await {
+                if event.name != "movewindowv2" {
+                    continue;
+                }
+                let window_info =
+                    current_window(&learn_conn, &learn_paths, &learn_window_info).await;
+                if event.address().as_deref() != Some(&window_info.address)
+                    || restored_by_us(&window_info.address)
+                {
+                    continue;
+                }
+                // movewindowv2>>ADDRESS,WORKSPACEID,WORKSPACENAME
+                let workspace_id = event.data.split(',').nth(1).and_then(|id| id.parse().ok());
+                if let Some(workspace_id) = workspace_id.filter(|id: &i32| *id > 0) {
+                    info!(
+                        "[Learn] Window moved to workspace {} by hand. Remembering it for '{}'.",
+                        workspace_id, window_info.class
+                    );
+                    let result = state::LearnedTargets::load()
+                        .and_then(|mut targets| targets.learn(&window_info.class, workspace_id));
+                    if let Err(e) = result {
+                        error!("Failed to save learned restore target: {}", e);
+                    }
+                    break;
+                }
+            }
+        }));
+    }
 
-    // 5. Start a background check to see if the window is closed or moved
-    let window_address = window_info.address.clone();
-    let check_task_exit_notify = Arc::clone(&exit_notify);
-    tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(2));
-        loop {
-            interval.tick().await;
-            match hyprctl::<Vec<WindowInfo>>("clients") {
-                Ok(clients) => {
-                    if let Some(client) = clients.iter().find(|c| c.address == window_address) {
-                        if client.workspace.id > 0 {
-                            println!("Window restored externally. Exiting.");
-                            check_task_exit_notify.notify_one();
-                            break;
+    // 5. Watch for the window being closed or moved by someone else.
+    // In a dry run the window never left its workspace, which would look like a restore.
+    if !args.dry_run {
+        tasks.push(tokio::spawn(watch_window_state(
+            Arc::clone(&arc_conn),
+            Arc::clone(&compositor),
+            paths.clone(),
+            window_info.clone(),
+            Arc::clone(&args),
+            Arc::clone(&exit_notify),
+        )));
+    }
+
+    // Go passive while the window sits there quietly, and back to active when it changes.
+    if let Some(secs) = args.passive_after {
+        let passive_conn = Arc::clone(&arc_conn);
+        let passive_status = Arc::clone(&status);
+        let passive_window_info = window_info.clone();
+        let passive_paths = paths.clone();
+        tasks.push(tokio::spawn(async move {
+            let idle = Duration::from_secs(secs);
+            let mut events = match events::EventStream::connect().await {
+                Ok(events) => Some(events),
+                Err(e) => {
+                    warn!("[Passive] Not watching for window changes: {}", e);
+                    None
+                }
+            };
+            // None while the icon is passive: nothing happens until the window changes again.
+            let mut deadline = Some(Instant::now() + idle);
+            loop {
+                let timer = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let event = async {
+                    match events.as_mut() {
+                        Some(events) => events.next().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = timer => {
+                        // An urgent window stays flagged; check again after another quiet period.
+                        if *passive_status.lock().unwrap() == ItemStatus::NeedsAttention {
+                            deadline = Some(Instant::now() + idle);
+                            continue;
+                        }
+                        info!("[Passive] Window has been quiet for {}s, marking icon passive.", secs);
+                        if let Err(e) = dbus::set_status(&passive_conn, &passive_paths.item, &passive_status, ItemStatus::Passive).await {
+                            error!("Failed to set passive status: {}", e);
+                        }
+                        deadline = None;
+                    }
+                    event = event => match event {
+                        Ok(Some(event)) => {
+                            // Urgent windows are flagged by the urgent watcher instead.
+                            let changed = event.name == "windowtitle" && {
+                                let window_info = current_window(&passive_conn, &passive_paths, &passive_window_info).await;
+                                event.address().as_deref() == Some(window_info.address.as_str())
+                            };
+                            let flagged = *passive_status.lock().unwrap() == ItemStatus::NeedsAttention;
+                            if changed {
+                                deadline = Some(Instant::now() + idle);
+                            }
+                            if changed && !flagged {
+                                if let Err(e) = dbus::set_status(&passive_conn, &passive_paths.item, &passive_status, ItemStatus::Active).await {
+                                    error!("Failed to set active status: {}", e);
+                                }
+                            }
+                        }
+                        Ok(None) | Err(_) => {
+                            warn!("[Passive] Event socket closed.");
+                            events = None;
                         }
-                    } else {
-                        println!("Window closed externally. Exiting.");
-                        check_task_exit_notify.notify_one();
-                        break;
                     }
                 }
+            }
+        }));
+    }
+
+    // Go passive while the special workspace is open, and back to active once it is hidden.
+    if args.passive_while_shown {
+        let shown_conn = Arc::clone(&arc_conn);
+        let shown_status = Arc::clone(&status);
+        let shown_path = paths.item.clone();
+        let shown_compositor = Arc::clone(&compositor);
+        tasks.push(tokio::spawn(async move {
+            let compositor = &*shown_compositor;
+            let mut events = match events::EventStream::connect().await {
+                Ok(events) => events,
                 Err(e) => {
-                    eprintln!("Error checking window state: {}", e);
-                    check_task_exit_notify.notify_one();
-                    break;
+                    warn!("[Passive] Not watching the special workspace: {}", e);
+                    return;
+                }
+            };
+            // Only undo a passive status this task set, not one from --passive-after.
+            let mut hidden_by_us = false;
+            while let Ok(Some(event)) = events.next().await {
+                if event.name != "activespecial" {
+                    continue;
+                }
+                let shown = match special_workspace_shown(compositor) {
+                    Ok(shown) => shown,
+                    Err(e) => {
+                        warn!("[Passive] Failed to check the special workspace: {}", e);
+                        continue;
+                    }
+                };
+                let current = *shown_status.lock().unwrap();
+                let new_status = if shown && current == ItemStatus::Active {
+                    hidden_by_us = true;
+                    ItemStatus::Passive
+                } else if !shown && hidden_by_us && current == ItemStatus::Passive {
+                    hidden_by_us = false;
+                    ItemStatus::Active
+                } else {
+                    continue;
+                };
+                if let Err(e) =
+                    dbus::set_status(&shown_conn, &shown_path, &shown_status, new_status).await
+                {
+                    error!("Failed to set {} status: {}", new_status.as_str(), e);
                 }
             }
-        }
-    });
+        }));
+    }
+
+    // Keep the auto-close countdown in the tooltip current.
+    if let Some(deadline) = auto_close_at {
+        let tooltip_conn = Arc::clone(&arc_conn);
+        let tooltip_path = paths.item.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut ticks = interval(Duration::from_secs(15));
+            while Instant::now() < deadline {
+                ticks.tick().await;
+                if let Err(e) = dbus::tool_tip_changed(&tooltip_conn, &tooltip_path).await {
+                    error!("Failed to emit NewToolTip: {}", e);
+                }
+            }
+        }));
+    }
 
     // 6. Wait for a notification to exit
-    println!("Application minimized to tray. Waiting for activation...");
+    let compositor = &*compositor;
+    info!("Application minimized to tray. Waiting for activation...");
+    let auto_close = async {
+        match auto_close_at {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    let auto_restore = async {
+        match args.restore_after.filter(|secs| *secs > 0) {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
     tokio::select! {
-        _ = tokio::signal::ctrl_c() => {
-            println!("\nInterrupted by Ctrl+C. Restoring window.");
-            let _ = hyprctl_dispatch(&format!(
-                "movetoworkspace {},address:{}",
-                window_info.workspace.id, window_info.address
-            ));
+        _ = auto_restore => {
+            let window_info = current_window(&arc_conn, &paths, &window_info).await;
+            info!("Restore timeout reached. Restoring window.");
+            let result = match learned_target {
+                Some(id) => restore_window(compositor, &window_info, id, &args),
+                None => args.restore(compositor, &window_info, None),
+            };
+            if let Err(e) = result {
+                error!("Failed to auto-restore window: {}", e);
+            }
+        }
+        _ = auto_close => {
+            let window_info = current_window(&arc_conn, &paths, &window_info).await;
+            info!("Auto-close timeout reached. Closing window (not restoring it).");
+            if let Err(e) = hyprctl_dispatch(compositor, &format!("closewindow address:{}", window_info.address)) {
+                error!("Failed to auto-close window: {}", e);
+            }
+        }
+        signal = shutdown_signal() => {
+            let window_info = current_window(&arc_conn, &paths, &window_info).await;
+            if args.restore_on_exit == RestoreOnExit::Never {
+                info!("Interrupted by {}. Leaving the window minimized.", signal);
+            } else {
+                info!("Interrupted by {}. Restoring window.", signal);
+                let _ = return_window(compositor, &window_info, window_info.workspace.id, None, false);
+            }
         }
         _ = exit_notify.notified() => {
-            println!("Exit notification received.");
+            debug!("Exit notification received.");
+            let window_info = current_window(&arc_conn, &paths, &window_info).await;
+            // Usually the window was restored or closed already; only a parked one is left to return.
+            let parked = get_window_by_address(compositor, &window_info.address)
+                .is_ok_and(|w| is_special_workspace(w.workspace.id, &w.workspace.name));
+            if args.restore_on_exit == RestoreOnExit::Always && parked {
+                info!("Restoring window before exiting.");
+                let _ = return_window(compositor, &window_info, window_info.workspace.id, None, false);
+            }
         }
     }
 
-    // 7. Cleanup is handled automatically when the connection is dropped.
-    println!("Exiting.");
+    // 7. Take the icon down again
+    for task in tasks {
+        task.abort();
+    }
+    // The window was dealt with, a panic from now on should leave it alone.
+    let window_info = current_window(&arc_conn, &paths, &window_info).await;
+    forget_on_panic(&window_info.address);
+    if let Err(e) = dbus::remove_item(&arc_conn, &paths).await {
+        error!("Failed to remove tray item: {}", e);
+    }
+    metrics::ITEMS_MINIMIZED.dec();
+    if let Err(e) = hide_empty_special_workspace(compositor) {
+        error!("Failed to hide the special workspace: {}", e);
+    }
+    if args.rename_workspace {
+        if let Err(e) = update_special_workspace_label(compositor) {
+            error!("Failed to rename special workspace: {}", e);
+        }
+    }
     Ok(())
 }
+
+/// Waits for one of the signals that should restore the window before exiting:
+/// Ctrl+C, SIGTERM (e.g. from systemd or `kill`) or SIGHUP. Returns its name.
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, Signal, SignalKind};
+
+    async fn recv(signal: Option<&mut Signal>) {
+        match signal {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    let mut terminate = signal(SignalKind::terminate())
+        .map_err(|e| warn!("Failed to listen for SIGTERM: {}", e))
+        .ok();
+    let mut hangup = signal(SignalKind::hangup())
+        .map_err(|e| warn!("Failed to listen for SIGHUP: {}", e))
+        .ok();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "Ctrl+C",
+        _ = recv(terminate.as_mut()) => "SIGTERM",
+        _ = recv(hangup.as_mut()) => "SIGHUP",
+    }
+}
+
+/// How long a panic waits for Sway to put each window back.
+const PANIC_RESTORE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The windows a panic puts back, by address, with the workspace to put them on.
+static PANIC_RESTORE: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Makes a panic put the windows back on their original workspaces before the
+/// default hook runs, so they are not stranded without a tray icon. The hook
+/// runs hyprctl itself, bypassing the logger, whose lock the panicking thread
+/// may hold. It is installed with the first windows; later ones join the list,
+/// and [`forget_on_panic`] takes them off again once they are restored.
+fn restore_on_panic(windows: &[WindowInfo]) {
+    static HOOK: std::sync::Once = std::sync::Once::new();
+    PANIC_RESTORE.lock().unwrap().extend(
+        windows
+            .iter()
+            .map(|w| (w.address.clone(), workspace_selector(w, w.workspace.id))),
+    );
+    HOOK.call_once(|| {
+        let instance = ipc::signature().ok();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // The panicking thread may be the one holding the lock.
+            let parked = match PANIC_RESTORE.try_lock() {
+                Ok(parked) => parked,
+                Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(std::sync::TryLockError::WouldBlock) => return default_hook(info),
+            };
+            for (address, workspace) in parked.iter() {
+                match ipc::backend() {
+                    ipc::Backend::Hyprland => {}
+                    ipc::Backend::Sway => {
+                        let restore = format!("movetoworkspace {},address:{}", workspace, address);
+                        let _ =
+                            sway::SwayBackend::from_env(PANIC_RESTORE_TIMEOUT).dispatch(&restore);
+                        continue;
+                    }
+                    // The panicking thread may hold the connection's lock, and the
+                    // compositor's own taskbar still lists minimized toplevels.
+                    ipc::Backend::Wlr => continue,
+                }
+                let mut command = Command::new("hyprctl");
+                if let Some(instance) = &instance {
+                    command.arg("--instance").arg(instance);
+                }
+                let _ = command
+                    .arg("dispatch")
+                    .arg(format!("movetoworkspace {},address:{}", workspace, address))
+                    .output();
+            }
+            default_hook(info);
+        }));
+    });
+}
+
+/// Makes a panic move the window under its new address.
+fn follow_on_panic(old: &str, new: &str) {
+    for (parked, _) in PANIC_RESTORE.lock().unwrap().iter_mut() {
+        if parked == old {
+            *parked = new.to_string();
+        }
+    }
+}
+
+/// Stops a panic from moving the window, e.g. because it was restored already.
+fn forget_on_panic(address: &str) {
+    PANIC_RESTORE
+        .lock()
+        .unwrap()
+        .retain(|(parked, _)| parked != address);
+}
+
+/// Minimizes every window whose class matches `pattern`, serving their tray
+/// icons side by side on one D-Bus connection.
+async fn minimize_all(
+    args: Arc<Args>,
+    compositor: Arc<dyn Compositor>,
+    pattern: &Regex,
+) -> Result<()> {
+    let windows: Vec<WindowInfo> = get_clients(&*compositor)?
+        .into_iter()
+        .filter(|c| pattern.is_match(&c.class))
+        .filter(|c| !is_own_window(c) && !is_special_workspace(c.workspace.id, &c.workspace.name))
+        .collect();
+    if windows.is_empty() {
+        anyhow::bail!("No window has a class matching '{}'", pattern);
+    }
+
+    let mut minimized = Vec::new();
+    for window_info in windows {
+        // A running daemon takes over the windows instead of a process of our own.
+        if !args.dry_run && daemon::forward(&args, &window_info.address).await? {
+            continue;
+        }
+        info!(
+            "Minimizing window: '{}' ({}) from workspace {}",
+            window_info.title, window_info.class, window_info.workspace.id
+        );
+        match minimize(&*compositor, &window_info, &args) {
+            Ok(()) => minimized.push(window_info),
+            Err(e) => error!("Failed to minimize '{}': {:#}", window_info.title, e),
+        }
+    }
+    if minimized.is_empty() {
+        return Ok(());
+    }
+    if args.restore_on_exit != RestoreOnExit::Never && !args.dry_run {
+        restore_on_panic(&minimized);
+    }
+
+    let bus_name = args.bus_name();
+    let arc_conn = Arc::new(dbus::connect(&bus_name).await?);
+    info!("D-Bus service '{}' is running.", bus_name);
+
+    let items: Vec<_> = minimized
+        .into_iter()
+        .zip(1..)
+        .map(|(window_info, n)| {
+            let paths = dbus::ItemPaths::numbered(&args, n);
+            // Items sharing a connection are registered by their object path.
+            let service = paths.item.clone();
+            let address = window_info.address.clone();
+            let item = run_item(
+                Arc::clone(&arc_conn),
+                Arc::clone(&compositor),
+                window_info,
+                Arc::clone(&args),
+                paths,
+                service,
+            );
+            (address, tokio::spawn(item))
+        })
+        .collect();
+    for (address, item) in items {
+        match item.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Tray icon for {} failed: {:#}", address, e),
+            Err(e) => error!("Tray icon for {} stopped unexpectedly: {}", address, e),
+        }
+    }
+    info!("Exiting.");
+    Ok(())
+}
+
+/// Rejects what hands window addresses to another process with `--backend wlr`,
+/// where they only hold on the connection of the process that looked them up.
+fn check_wlr_addresses(args: &Args) -> Result<()> {
+    let feature = match &args.command {
+        _ if args.daemon => "--daemon",
+        _ if args.solo_on_restore => "--solo-on-restore",
+        Some(Commands::FocusMode { .. }) => "focus-mode",
+        Some(Commands::Restore { addresses, .. }) if !addresses.is_empty() => {
+            "Restoring by address"
+        }
+        _ => return Ok(()),
+    };
+    anyhow::bail!(
+        "{} is not supported with --backend wlr, whose window addresses only hold within one process.",
+        feature
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Arc::new(config::parse_args()?);
+    if let Some(shell) = args.generate_completions {
+        print!("{}", completions::generate(shell, &Args::command()));
+        return Ok(());
+    }
+    logging::init(args.log_level(), args.no_color, args.log_file.as_deref())?;
+    SPECIAL_WORKSPACE
+        .set(format!("special:{}", args.special_workspace))
+        .unwrap();
+    HYPRCTL_RETRIES.set(args.hyprctl_retries).unwrap();
+    DRY_RUN.store(args.dry_run, Ordering::Relaxed);
+    let compositor = ipc::connect(
+        args.backend,
+        Duration::from_millis(args.hyprctl_timeout),
+        args.instance.clone(),
+    );
+    if args.backend == ipc::Backend::Wlr {
+        check_wlr_addresses(&args)?;
+    }
+
+    match args.command {
+        Some(Commands::ProbeDbusmenu) => {
+            // The sample window isn't real, and peeking shouldn't move the user's workspaces.
+            DRY_RUN.store(true, Ordering::Relaxed);
+            dbus::probe_dbusmenu(args, compositor).await;
+            return Ok(());
+        }
+        Some(Commands::Restore {
+            ref addresses,
+            ref class,
+        }) => return restore_matching(&*compositor, addresses, class.as_deref()),
+        Some(Commands::List) => return list_minimized(&*compositor),
+        Some(Commands::FocusMode { off }) => return focus_mode(&*compositor, off),
+        Some(Commands::InstallService { ref dir }) => return service::install(dir.as_deref()),
+        None => {}
+    }
+
+    if args.daemon {
+        return daemon::run(args, compositor).await;
+    }
+    if args.module {
+        return module::run(args, compositor).await;
+    }
+
+    if let (true, Some(pattern)) = (args.all, &args.class) {
+        return minimize_all(Arc::clone(&args), compositor, pattern).await;
+    }
+
+    // 1. Get window info based on CLI arguments
+    let window_info = select_window(&*compositor, &args)?;
+
+    // The minimizer serving this window notices it is gone and exits by itself.
+    if args.toggle && is_special_workspace(window_info.workspace.id, &window_info.workspace.name) {
+        info!("Window is already minimized, restoring it instead.");
+        let id = RestoreTarget::Current.resolve(&*compositor, &window_info, None)?;
+        return restore_window(&*compositor, &window_info, id, &args);
+    }
+
+    info!(
+        "Minimizing window: '{}' ({}) from workspace {}",
+        window_info.title, window_info.class, window_info.workspace.id
+    );
+
+    if let Some(cycles) = args.exit_after {
+        return soak(&*compositor, &window_info, cycles);
+    }
+
+    // A running daemon takes over the window instead of a process of our own.
+    if !args.dry_run && daemon::forward(&args, &window_info.address).await? {
+        return Ok(());
+    }
+
+    // 2. Move the window to the special workspace
+    minimize(&*compositor, &window_info, &args)?;
+    if args.restore_on_exit != RestoreOnExit::Never && !args.dry_run {
+        restore_on_panic(std::slice::from_ref(&window_info));
+    }
+
+    let bus_name = args.bus_name();
+    let arc_conn = Arc::new(dbus::connect(&bus_name).await?);
+    info!("D-Bus service '{}' is running.", bus_name);
+
+    // Answer commands on the control socket, if systemd passed one or one was requested.
+    let control_socket = match control::ControlSocket::from_systemd()? {
+        Some(socket) => {
+            info!("[Control] Listening on the socket passed by systemd");
+            Some(socket)
+        }
+        None => match &args.control_socket {
+            Some(path) => {
+                let socket = control::ControlSocket::bind(path).await?;
+                info!("[Control] Listening on {}", path.display());
+                Some(socket)
+            }
+            None => None,
+        },
+    };
+    if let Some(socket) = control_socket {
+        let control_window_info = window_info.clone();
+        let control_compositor = Arc::clone(&compositor);
+        tokio::spawn(socket.serve(move |command| match command {
+            "metrics" => metrics::render(),
+            "restore-all" => {
+                let windows = [control_window_info.clone()];
+                // The item notices the window is gone and exits by itself.
+                let restored = restore_all(&*control_compositor, &windows);
+                format!("restored {} of {} window(s)\n", restored, windows.len())
+            }
+            _ => format!("error: unknown command '{}'\n", command),
+        }));
+    }
+
+    let paths = dbus::ItemPaths::single(&args);
+    run_item(arc_conn, compositor, window_info, args, paths, bus_name).await?;
+
+    info!("Exiting.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipc::fake::FakeCompositor;
+    use serde_json::json;
+
+    /// A client as `hyprctl clients` reports it.
+    fn client(address: &str, class: &str, workspace: (i32, &str)) -> serde_json::Value {
+        json!({
+            "address": address,
+            "workspace": {"id": workspace.0, "name": workspace.1},
+            "title": format!("{} window", class),
+            "class": class,
+            "pid": 100,
+            "pinned": false,
+            "xwayland": false,
+            "monitor": 0,
+            "floating": false,
+            "at": [0, 0],
+            "size": [800, 600],
+            "fullscreen": 0,
+            "focusHistoryID": 0,
+        })
+    }
+
+    fn addresses(windows: Vec<WindowInfo>) -> Vec<String> {
+        windows.into_iter().map(|w| w.address).collect()
+    }
+
+    #[test]
+    fn toggles_are_not_repeatable() {
+        assert!(repeatable(&[
+            "movetoworkspacesilent 3,address:0x1",
+            "focuswindow address:0x1"
+        ]));
+        assert!(!repeatable(&["pin address:0x1"]));
+        assert!(!repeatable(&[
+            "movetoworkspace 3,address:0x1",
+            "togglespecialworkspace minimized"
+        ]));
+        // Only the dispatcher counts, not a window title that happens to match.
+        assert!(repeatable(&["focuswindow title:pin"]));
+    }
+
+    #[test]
+    fn requests_run_once_more_per_retry() {
+        let attempts = std::cell::Cell::new(0);
+        let failing = || -> Result<()> {
+            attempts.set(attempts.get() + 1);
+            Err(anyhow!("no reply"))
+        };
+        assert!(retry("pin", 0, failing).is_err());
+        assert_eq!(attempts.get(), 1);
+        attempts.set(0);
+        assert!(retry("clients", 1, failing).is_err());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn get_clients_skips_our_own_windows() {
+        let compositor = FakeCompositor::default();
+        compositor.reply(
+            "clients",
+            json!([
+                client("0x1", "firefox", (1, "1")),
+                client("0x2", "hyprland-minimizer", (1, "1")),
+                client("0x3", "hyprland-minimizer-picker", (1, "1")),
+                client("0x4", "hyprland-minimizerish", (1, "1")),
+            ]),
+        );
+
+        let clients = get_clients(&compositor).unwrap();
+
+        assert_eq!(addresses(clients), ["0x1", "0x4"]);
+        // Selecting it by address goes through the same filter.
+        assert!(get_window_by_address(&compositor, "0x2").is_err());
+    }
+
+    #[test]
+    fn recognizes_only_our_own_count_label() {
+        assert_eq!(special_workspace_label(3), "Minimized (3)");
+        assert!(is_special_workspace(-98, "Minimized (3)"));
+        // Another instance's special workspace, under its own label.
+        assert!(!is_special_workspace(-97, "Stash (3)"));
+        assert!(!is_special_workspace(-98, "Minimized (many)"));
+        assert!(!is_special_workspace(-98, "Minimized (3) and more"));
+    }
+
+    #[test]
+    fn pinned_windows_are_unpinned_while_minimized_and_pinned_again() {
+        let compositor = FakeCompositor::default();
+        let mut pinned = client("0x1", "player", (2, "2"));
+        pinned["pinned"] = json!(true);
+        compositor.reply("clients", json!([pinned]));
+        let window_info = get_window_by_address(&compositor, "0x1").unwrap();
+        assert!(window_info.pinned);
+
+        move_to_special_workspace(&compositor, &window_info).unwrap();
+        return_window(&compositor, &window_info, 2, None, false).unwrap();
+
+        assert_eq!(
+            compositor.dispatched(),
+            [
+                "pin address:0x1",
+                "movetoworkspacesilent special:minimized,address:0x1",
+                "movetoworkspace 2,address:0x1",
+                "pin address:0x1",
+            ]
+        );
+    }
+
+    #[test]
+    fn focus_history_picks_the_window_at_the_index() {
+        let compositor = FakeCompositor::default();
+        let mut clients: Vec<serde_json::Value> = ["0x1", "0x2", "0x3"]
+            .iter()
+            .map(|address| client(address, "term", (1, "1")))
+            .collect();
+        for (client, index) in clients.iter_mut().zip([2, 0, 1]) {
+            client["focusHistoryID"] = json!(index);
+        }
+        compositor.reply("clients", json!(clients));
+
+        assert_eq!(
+            get_window_by_focus_history(&compositor, 0).unwrap().address,
+            "0x2"
+        );
+        assert_eq!(
+            get_window_by_focus_history(&compositor, 1).unwrap().address,
+            "0x3"
+        );
+        let error = get_window_by_focus_history(&compositor, 3).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No window at focus history index 3; the history has indices 0 to 2"
+        );
+    }
+
+    #[test]
+    fn classifies_the_workspaces_a_window_can_be_on() {
+        // Our special workspace, under its own name and under its count label.
+        assert!(is_special_workspace(-98, "special:minimized"));
+        assert!(is_special_workspace(-98, "Minimized (2)"));
+        // Named and numbered regular workspaces.
+        assert!(!is_special_workspace(3, "web"));
+        assert!(!is_special_workspace(1, "1"));
+        // Somebody else's special workspace, e.g. a scratchpad.
+        assert!(!is_special_workspace(-97, "special:scratchpad"));
+        // A regular workspace that happens to carry our label.
+        assert!(!is_special_workspace(4, "Minimized (2)"));
+    }
+
+    #[test]
+    fn minimized_window_is_found_only_on_our_special_workspace() {
+        let compositor = FakeCompositor::default();
+        compositor.reply(
+            "clients",
+            json!([
+                client("0x1", "term", (-98, "special:minimized")),
+                client("0x2", "term", (-98, "Minimized (2)")),
+                client("0x3", "term", (-97, "special:scratchpad")),
+                client("0x4", "term", (3, "web")),
+            ]),
+        );
+
+        assert!(minimized_window(&compositor, "0x1").is_some());
+        assert!(minimized_window(&compositor, "0x2").is_some());
+        assert!(minimized_window(&compositor, "0x3").is_none());
+        assert!(minimized_window(&compositor, "0x4").is_none());
+        // Closed.
+        assert!(minimized_window(&compositor, "0x5").is_none());
+    }
+
+    #[test]
+    fn fullscreen_dispatches_match_the_saved_state() {
+        let window = |fullscreen| WindowInfo {
+            address: "0x1".to_string(),
+            fullscreen,
+            ..WindowInfo::default()
+        };
+
+        assert!(fullscreen_dispatches(&window(0)).is_empty());
+        assert_eq!(
+            fullscreen_dispatches(&window(1)),
+            ["focuswindow address:0x1", "fullscreen 1"]
+        );
+        assert_eq!(
+            fullscreen_dispatches(&window(2)),
+            ["focuswindow address:0x1", "fullscreen 0"]
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Process-wide counters, exported in the Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A monotonically increasing counter or a gauge backed by an atomic.
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    value: AtomicU64,
+}
+
+impl Metric {
+    const fn counter(name: &'static str, help: &'static str) -> Metric {
+        Metric {
+            name,
+            help,
+            kind: "counter",
+            value: AtomicU64::new(0),
+        }
+    }
+
+    const fn gauge(name: &'static str, help: &'static str) -> Metric {
+        Metric {
+            name,
+            help,
+            kind: "gauge",
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Decrements a gauge, saturating at zero.
+    pub fn dec(&self) {
+        let _ = self
+            .value
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1));
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+pub static ITEMS_MINIMIZED: Metric = Metric::gauge(
+    "hyprland_minimizer_items_minimized",
+    "Number of windows currently minimized to the tray.",
+);
+pub static MINIMIZED_TOTAL: Metric = Metric::counter(
+    "hyprland_minimizer_minimized_total",
+    "Total number of windows minimized.",
+);
+pub static RESTORED_TOTAL: Metric = Metric::counter(
+    "hyprland_minimizer_restored_total",
+    "Total number of windows restored from the tray.",
+);
+pub static SUBPROCESS_SPAWNS_TOTAL: Metric = Metric::counter(
+    "hyprland_minimizer_subprocess_spawns_total",
+    "Total number of hyprctl processes spawned.",
+);
+pub static DBUS_RECONNECTS_TOTAL: Metric = Metric::counter(
+    "hyprland_minimizer_dbus_reconnects_total",
+    "Total number of re-registrations with the StatusNotifierWatcher.",
+);
+
+static ALL: [&Metric; 5] = [
+    &ITEMS_MINIMIZED,
+    &MINIMIZED_TOTAL,
+    &RESTORED_TOTAL,
+    &SUBPROCESS_SPAWNS_TOTAL,
+    &DBUS_RECONNECTS_TOTAL,
+];
+
+/// Renders all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for metric in ALL {
+        let _ = writeln!(out, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(out, "# TYPE {} {}", metric.name, metric.kind);
+        let _ = writeln!(out, "{} {}", metric.name, metric.get());
+    }
+    out
+}
@@ -0,0 +1,138 @@
+//! `--module` mode: a status line for a Waybar `custom` module instead of tray
+//! icons. Prints one JSON object per change of the minimized windows, e.g.
+//! `{"text":"2","tooltip":"Inbox\nChat","class":"minimized"}`; the text is
+//! empty while no window is minimized, which makes Waybar hide the module.
+//!
+//! Clicks come in as signals: SIGUSR1 restores the most recently used minimized
+//! window, SIGUSR2 restores all of them, e.g.
+//! `"on-click": "pkill -USR1 -f 'hyprland-minimizer --module'"`.
+
+use crate::ipc::Compositor;
+use crate::{
+    events, find_special_workspace, get_clients, move_and_focus, Args, RestoreTarget, WindowInfo,
+};
+use anyhow::Result;
+use log::{error, info, warn};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{interval, Duration};
+
+/// Returns the minimized windows, most recently used first.
+fn minimized_windows(compositor: &dyn Compositor) -> Result<Vec<WindowInfo>> {
+    let Some(special) = find_special_workspace(compositor)? else {
+        return Ok(Vec::new());
+    };
+    let mut windows: Vec<WindowInfo> = get_clients(compositor)?
+        .into_iter()
+        .filter(|c| c.workspace.id == special.id)
+        .collect();
+    windows.sort_by_key(|w| w.focus_history_id);
+    Ok(windows)
+}
+
+/// Renders the Waybar status line for the minimized windows.
+fn status_line(windows: &[WindowInfo]) -> String {
+    let (text, class) = match windows.len() {
+        0 => (String::new(), "empty"),
+        n => (n.to_string(), "minimized"),
+    };
+    let tooltip: Vec<&str> = windows.iter().map(|w| w.title.as_str()).collect();
+    serde_json::json!({
+        "text": text,
+        "tooltip": tooltip.join("\n"),
+        "class": class,
+    })
+    .to_string()
+}
+
+/// Restores the given windows to the active workspace.
+fn restore(compositor: &dyn Compositor, windows: &[WindowInfo]) {
+    for window_info in windows {
+        let result = RestoreTarget::Current
+            .resolve(compositor, window_info, None)
+            .and_then(|id| move_and_focus(compositor, window_info, id));
+        if let Err(e) = result {
+            error!("Failed to restore window '{}': {}", window_info.title, e);
+        }
+    }
+}
+
+/// Hyprland events after which the minimized windows may have changed.
+const RELEVANT_EVENTS: [&str; 6] = [
+    "openwindow",
+    "closewindow",
+    "movewindow",
+    "movewindowv2",
+    "windowtitle",
+    "windowtitlev2",
+];
+
+/// Prints status lines until interrupted.
+pub async fn run(args: Arc<Args>, compositor: Arc<dyn Compositor>) -> Result<()> {
+    let compositor = &*compositor;
+    let mut restore_last = signal(SignalKind::user_defined1())?;
+    let mut restore_every = signal(SignalKind::user_defined2())?;
+    let mut events = match events::EventStream::connect().await {
+        Ok(events) => Some(events),
+        Err(e) => {
+            warn!(
+                "[Module] Event socket unavailable ({}), falling back to polling.",
+                e
+            );
+            None
+        }
+    };
+    let mut poll = interval(Duration::from_millis(args.poll_interval));
+    let mut last_line = None;
+    let mut refresh = true;
+
+    loop {
+        if refresh {
+            match minimized_windows(compositor) {
+                Ok(windows) => {
+                    let line = status_line(&windows);
+                    if last_line.as_ref() != Some(&line) {
+                        println!("{}", line);
+                        last_line = Some(line);
+                    }
+                }
+                Err(e) => error!("[Module] Failed to list minimized windows: {}", e),
+            }
+        }
+
+        let polling = events.is_none();
+        let event = async {
+            match events.as_mut() {
+                Some(events) => events.next().await,
+                None => std::future::pending().await,
+            }
+        };
+        refresh = tokio::select! {
+            _ = restore_last.recv() => {
+                info!("[Module] Restoring the most recent window.");
+                match minimized_windows(compositor) {
+                    Ok(windows) => restore(compositor, &windows[..windows.len().min(1)]),
+                    Err(e) => error!("[Module] Failed to list minimized windows: {}", e),
+                }
+                true
+            }
+            _ = restore_every.recv() => {
+                info!("[Module] Restoring all windows.");
+                match minimized_windows(compositor) {
+                    Ok(windows) => restore(compositor, &windows),
+                    Err(e) => error!("[Module] Failed to list minimized windows: {}", e),
+                }
+                true
+            }
+            event = event => match event {
+                Ok(Some(event)) => RELEVANT_EVENTS.contains(&event.name.as_str()),
+                Ok(None) | Err(_) => {
+                    warn!("[Module] Event socket closed, falling back to polling.");
+                    events = None;
+                    true
+                }
+            },
+            _ = poll.tick(), if polling => true,
+        };
+    }
+}
@@ -0,0 +1,66 @@
+//! Per-desktop compatibility settings for the StatusNotifierItem host.
+//! SNI hosts disagree on details of the protocol, so everything we tune for a
+//! particular host lives here instead of being scattered across the D-Bus code.
+
+use std::time::Duration;
+
+/// The well-known bus name of the KDE StatusNotifierWatcher, used by Waybar and most bars.
+pub const KDE_WATCHER: &str = "org.kde.StatusNotifierWatcher";
+
+/// The freedesktop name of the StatusNotifierWatcher, which some hosts take instead.
+pub const FREEDESKTOP_WATCHER: &str = "org.freedesktop.StatusNotifierWatcher";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrayQuirks {
+    /// How long to wait after a watcher appears before registering with it.
+    pub registration_delay: Duration,
+    /// Whether the item only offers a menu, so hosts show it on primary click.
+    pub item_is_menu: bool,
+    /// The bus names of the StatusNotifierWatchers to register with, whichever are present.
+    pub watcher_names: Vec<String>,
+}
+
+impl Default for TrayQuirks {
+    fn default() -> Self {
+        TrayQuirks {
+            // Waybar needs a moment after taking the watcher name before it accepts items.
+            registration_delay: Duration::from_millis(100),
+            item_is_menu: false,
+            watcher_names: vec![KDE_WATCHER.to_string(), FREEDESKTOP_WATCHER.to_string()],
+        }
+    }
+}
+
+impl TrayQuirks {
+    /// Picks the quirks profile for a desktop, as named in `XDG_CURRENT_DESKTOP`.
+    /// The variable may hold a colon-separated list; the first known entry wins.
+    pub fn for_desktop(desktop: &str) -> TrayQuirks {
+        for name in desktop.split(':') {
+            match name.to_ascii_lowercase().as_str() {
+                "kde" => {
+                    return TrayQuirks {
+                        registration_delay: Duration::ZERO,
+                        ..TrayQuirks::default()
+                    }
+                }
+                // The AppIndicator hosts on GNOME and Unity never send Activate
+                // and always pop up the menu instead.
+                "gnome" | "unity" => {
+                    return TrayQuirks {
+                        registration_delay: Duration::ZERO,
+                        item_is_menu: true,
+                        ..TrayQuirks::default()
+                    }
+                }
+                "hyprland" => return TrayQuirks::default(),
+                _ => {}
+            }
+        }
+        TrayQuirks::default()
+    }
+
+    /// Picks the quirks profile for the running desktop session.
+    pub fn detect() -> TrayQuirks {
+        TrayQuirks::for_desktop(&std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default())
+    }
+}
@@ -0,0 +1,99 @@
+//! Systemd user units that start the daemon on first use: the socket unit
+//! listens on the daemon socket and hands it over when the first window gets
+//! minimized, see `ControlSocket::from_systemd`.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+const SOCKET_UNIT: &str = "hyprland-minimizer.socket";
+const SERVICE_UNIT: &str = "hyprland-minimizer.service";
+
+/// Returns the directory systemd looks for user units in, `$XDG_CONFIG_HOME/systemd/user`.
+fn unit_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?).join(".config"),
+    };
+    Ok(base.join("systemd").join("user"))
+}
+
+/// The socket unit, listening where invocations look for the daemon by default.
+fn socket_unit() -> String {
+    format!(
+        "[Unit]\n\
+         Description=Socket of the hyprland-minimizer daemon\n\
+         \n\
+         [Socket]\n\
+         ListenStream=%t/hyprland-minimizer.sock\n\
+         SocketMode=0600\n\
+         Service={}\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n",
+        SERVICE_UNIT
+    )
+}
+
+/// The service unit running `exe` as the daemon.
+fn service_unit(exe: &Path) -> String {
+    // `%` starts a specifier in unit files, and quotes keep paths with spaces whole.
+    let exe = exe.display().to_string().replace('%', "%%");
+    format!(
+        "[Unit]\n\
+         Description=Tray icons for minimized Hyprland windows\n\
+         Requires={socket}\n\
+         After={socket} graphical-session.target\n\
+         PartOf=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         ExecStart=\"{exe}\" --daemon\n\
+         Restart=on-failure\n",
+        socket = SOCKET_UNIT,
+        exe = exe
+    )
+}
+
+/// Writes both units to `dir`, or the user unit directory, for this executable.
+pub fn install(dir: Option<&Path>) -> Result<()> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => unit_dir()?,
+    };
+    let exe = std::env::current_exe().context("Failed to locate the minimizer executable.")?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for (name, contents) in [
+        (SOCKET_UNIT, socket_unit()),
+        (SERVICE_UNIT, service_unit(&exe)),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    println!(
+        "Enable it with: systemctl --user daemon-reload && systemctl --user enable --now {}",
+        SOCKET_UNIT
+    );
+    println!(
+        "The daemon needs HYPRLAND_INSTANCE_SIGNATURE in the user manager, e.g. from \
+         `exec-once = systemctl --user import-environment HYPRLAND_INSTANCE_SIGNATURE`."
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_runs_the_daemon_from_the_given_path() {
+        let unit = service_unit(Path::new("/opt/my tools/100%/hyprland-minimizer"));
+        assert!(unit.contains("ExecStart=\"/opt/my tools/100%%/hyprland-minimizer\" --daemon\n"));
+        assert!(unit.contains(&format!("Requires={}\n", SOCKET_UNIT)));
+    }
+
+    #[test]
+    fn socket_listens_on_the_default_daemon_socket() {
+        assert!(socket_unit().contains("ListenStream=%t/hyprland-minimizer.sock\n"));
+    }
+}
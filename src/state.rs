@@ -0,0 +1,99 @@
+//! Persistent state that outlives a single minimizer process.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Returns the directory state files are kept in, `$XDG_STATE_HOME/hyprland-minimizer`.
+fn state_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?)
+            .join(".local")
+            .join("state"),
+    };
+    Ok(base.join("hyprland-minimizer"))
+}
+
+/// The workspaces users have manually restored windows to, keyed by window class.
+#[derive(Debug, Default)]
+pub struct LearnedTargets {
+    targets: HashMap<String, i32>,
+}
+
+impl LearnedTargets {
+    fn path() -> Result<PathBuf> {
+        Ok(state_dir()?.join("restore-targets.json"))
+    }
+
+    /// Loads the learned targets. A missing file yields an empty set.
+    pub fn load() -> Result<LearnedTargets> {
+        let path = LearnedTargets::path()?;
+        let targets = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(LearnedTargets { targets })
+    }
+
+    pub fn get(&self, class: &str) -> Option<i32> {
+        self.targets.get(class).copied()
+    }
+
+    /// Records a workspace for the class and writes the file back.
+    pub fn learn(&mut self, class: &str, workspace_id: i32) -> Result<()> {
+        self.targets.insert(class.to_string(), workspace_id);
+        let path = LearnedTargets::path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(&self.targets)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// The windows `focus-mode` minimized, so `focus-mode --off` brings back exactly those.
+#[derive(Debug, Default)]
+pub struct FocusModeWindows {
+    pub addresses: Vec<String>,
+}
+
+impl FocusModeWindows {
+    fn path() -> Result<PathBuf> {
+        Ok(state_dir()?.join("focus-mode.json"))
+    }
+
+    /// Loads the recorded windows. A missing file means focus mode is off.
+    pub fn load() -> Result<FocusModeWindows> {
+        let path = FocusModeWindows::path()?;
+        let addresses = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(FocusModeWindows { addresses })
+    }
+
+    /// Writes the recorded windows back, removing the file when there are none.
+    pub fn save(&self) -> Result<()> {
+        let path = FocusModeWindows::path()?;
+        if self.addresses.is_empty() {
+            return match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+                }
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(&self.addresses)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
@@ -0,0 +1,832 @@
+//! The Sway backend of `--backend sway`. Sway speaks the i3 IPC protocol on
+//! the socket in `$SWAYSOCK`; this module answers the minimizer's Hyprland
+//! queries and dispatches through it, so everything above [`Compositor`]
+//! stays the same:
+//!
+//! - windows are addressed by their container id, in hex like Hyprland's addresses;
+//! - the scratchpad stands in for the special workspace;
+//! - workspaces without a number get a stable id derived from their name;
+//! - window events are turned into the event lines Hyprland would send.
+//!
+//! Dispatches without a Sway counterpart, e.g. `renameworkspace`, are answered
+//! with an error instead of `ok`, which callers report like a rejected dispatch.
+
+use crate::ipc::Compositor;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// --- i3 IPC ---
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+/// The type of window events; events have the highest bit set.
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+/// Returns the path of Sway's IPC socket.
+fn socket_path() -> Result<PathBuf> {
+    std::env::var_os("SWAYSOCK")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("SWAYSOCK is not set — are you running inside a Sway session?"))
+}
+
+/// Frames a message: the magic string, the payload length and the message type,
+/// both in native byte order, then the payload.
+fn encode(kind: u32, payload: &str) -> Vec<u8> {
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    message
+}
+
+/// Splits a message header into the payload length and the message type.
+fn decode_header(header: &[u8; 14]) -> Result<(usize, u32)> {
+    if &header[..6] != MAGIC {
+        bail!("Sway sent a message without the i3-ipc magic");
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+    let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    Ok((length, kind))
+}
+
+/// Talks to Sway's IPC socket, one connection per request.
+pub struct SwayBackend {
+    timeout: Duration,
+}
+
+impl SwayBackend {
+    /// Returns the backend for the Sway session in the environment. A missing
+    /// `SWAYSOCK` is reported by the first request.
+    pub fn from_env(timeout: Duration) -> SwayBackend {
+        SwayBackend { timeout }
+    }
+
+    /// Sends one message and returns the parsed reply.
+    fn request(&self, kind: u32, payload: &str) -> Result<Value> {
+        let path = socket_path()?;
+        let mut stream = UnixStream::connect(&path)
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+        // A hung compositor would otherwise block the caller forever.
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream
+            .write_all(&encode(kind, payload))
+            .with_context(|| format!("Failed to send '{}' to Sway", payload))?;
+        let mut header = [0u8; 14];
+        stream
+            .read_exact(&mut header)
+            .context("Failed to read Sway's reply")?;
+        let (length, _) = decode_header(&header)?;
+        let mut reply = vec![0u8; length];
+        stream
+            .read_exact(&mut reply)
+            .context("Failed to read Sway's reply")?;
+        serde_json::from_slice(&reply).context("Sway sent a reply that is not JSON")
+    }
+
+    fn tree(&self) -> Result<Value> {
+        self.request(GET_TREE, "")
+    }
+
+    /// Runs a Sway command and returns `ok`, or the errors Sway gave.
+    fn run(&self, command: &str) -> Result<String> {
+        let results = self.request(RUN_COMMAND, command)?;
+        let errors: Vec<&str> = results
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|r| !r["success"].as_bool().unwrap_or(false))
+            .map(|r| r["error"].as_str().unwrap_or("command failed"))
+            .collect();
+        if errors.is_empty() {
+            Ok("ok".to_string())
+        } else {
+            Ok(errors.join("; "))
+        }
+    }
+}
+
+impl Compositor for SwayBackend {
+    fn query(&self, command: &str) -> Result<Vec<u8>> {
+        let reply = match command {
+            "clients" => Value::from(clients(&self.tree()?)),
+            "activewindow" => active_window(&self.tree()?),
+            "activeworkspace" => active_workspace(&self.request(GET_WORKSPACES, "")?),
+            "workspaces" => Value::from(workspaces(&self.tree()?)),
+            "monitors" => Value::from(monitors(&self.tree()?, &self.request(GET_OUTPUTS, "")?)),
+            _ => bail!("'{}' is not available on Sway", command),
+        };
+        Ok(reply.to_string().into_bytes())
+    }
+
+    fn dispatch(&self, command: &str) -> Result<String> {
+        match translate(command, &self.tree()?) {
+            Ok(sway_command) => self.run(&sway_command),
+            Err(reply) => Ok(reply),
+        }
+    }
+
+    fn dispatch_batch(&self, commands: &[&str]) -> Result<String> {
+        // Like a Hyprland batch, the replies run together and stop at the first error.
+        let mut replies = String::new();
+        for command in commands {
+            let reply = self.dispatch(command)?;
+            let failed = reply != "ok";
+            replies.push_str(&reply);
+            if failed {
+                break;
+            }
+        }
+        Ok(replies)
+    }
+}
+
+// --- Tree ---
+
+/// The name of the workspace Sway keeps the scratchpad in.
+const SCRATCHPAD: &str = "__i3_scratch";
+/// The id the scratchpad goes by; negative, like Hyprland's special workspaces.
+const SCRATCHPAD_ID: i32 = -99;
+/// Where the ids of workspaces without a number start.
+const NAMED_WORKSPACE_BASE: i32 = 1_000_000;
+
+/// Returns the window address of a container id.
+fn address(id: i64) -> String {
+    format!("0x{:x}", id)
+}
+
+/// Returns the container id of a window address.
+fn container_id(address: &str) -> Option<i64> {
+    i64::from_str_radix(address.strip_prefix("0x")?, 16).ok()
+}
+
+/// Returns the id a workspace goes by: its number, or one derived from its name.
+fn workspace_id(workspace: &Value) -> i32 {
+    let name = workspace["name"].as_str().unwrap_or_default();
+    if name == SCRATCHPAD {
+        return SCRATCHPAD_ID;
+    }
+    match workspace["num"].as_i64() {
+        Some(num) if num >= 0 => num as i32,
+        // FNV-1a, which stays the same across runs unlike the std hasher.
+        _ => {
+            let hash = name.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+                (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+            });
+            NAMED_WORKSPACE_BASE + (hash % 1_000_000) as i32
+        }
+    }
+}
+
+/// Returns the name a workspace goes by; the scratchpad's is the special workspace's.
+fn workspace_name(workspace: &Value) -> String {
+    match workspace["name"].as_str().unwrap_or_default() {
+        SCRATCHPAD => crate::special_workspace().to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The children of a tree node, tiled and floating.
+fn children(node: &Value) -> impl Iterator<Item = &Value> {
+    let tiled = node["nodes"].as_array().into_iter().flatten();
+    tiled.chain(node["floating_nodes"].as_array().into_iter().flatten())
+}
+
+/// Returns true for the containers holding a window, as opposed to splits and tabs.
+fn is_window(node: &Value) -> bool {
+    matches!(node["type"].as_str(), Some("con" | "floating_con"))
+        && !node["pid"].is_null()
+        && children(node).next().is_none()
+}
+
+/// A window of the tree with the workspace it is on and the index of its output.
+struct Window<'a> {
+    node: &'a Value,
+    workspace: &'a Value,
+    monitor: i32,
+}
+
+/// The outputs of the tree, leaving out the one holding the scratchpad.
+fn outputs(tree: &Value) -> impl Iterator<Item = &Value> {
+    children(tree).filter(|o| o["name"].as_str() != Some("__i3"))
+}
+
+/// All workspaces of the tree, the scratchpad included.
+fn tree_workspaces(tree: &Value) -> impl Iterator<Item = &Value> {
+    children(tree)
+        .flat_map(children)
+        .filter(|w| w["type"].as_str() == Some("workspace"))
+}
+
+fn windows(tree: &Value) -> Vec<Window<'_>> {
+    fn collect<'a>(node: &'a Value, workspace: &'a Value, monitor: i32, out: &mut Vec<Window<'a>>) {
+        if is_window(node) {
+            out.push(Window {
+                node,
+                workspace,
+                monitor,
+            });
+        }
+        for child in children(node) {
+            collect(child, workspace, monitor, out);
+        }
+    }
+    let mut windows = Vec::new();
+    for workspace in tree_workspaces(tree) {
+        let output = workspace["output"].as_str();
+        let monitor = outputs(tree)
+            .position(|o| o["name"].as_str() == output)
+            .map_or(-1, |index| index as i32);
+        collect(workspace, workspace, monitor, &mut windows);
+    }
+    windows
+}
+
+/// Returns the windows most recently focused first, following each node's focus stack.
+fn focus_order(tree: &Value) -> Vec<i64> {
+    fn visit(node: &Value, out: &mut Vec<i64>) {
+        if is_window(node) {
+            out.extend(node["id"].as_i64());
+        }
+        let stack: Vec<i64> = node["focus"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_i64)
+            .collect();
+        let rank = |child: &&Value| {
+            let id = child["id"].as_i64();
+            stack
+                .iter()
+                .position(|&f| Some(f) == id)
+                .unwrap_or(stack.len())
+        };
+        let mut children: Vec<&Value> = children(node).collect();
+        children.sort_by_key(rank);
+        for child in children {
+            visit(child, out);
+        }
+    }
+    let mut order = Vec::new();
+    visit(tree, &mut order);
+    order
+}
+
+/// Describes a window the way `hyprctl -j clients` does.
+fn client(window: &Window, focus_history_id: i32) -> Value {
+    let node = window.node;
+    let title = node["name"].as_str().unwrap_or_default();
+    // XWayland windows have no app id, only an X11 class.
+    let class = node["app_id"]
+        .as_str()
+        .filter(|id| !id.is_empty())
+        .or_else(|| node["window_properties"]["class"].as_str())
+        .unwrap_or_default();
+    let rect = &node["rect"];
+    json!({
+        "address": address(node["id"].as_i64().unwrap_or_default()),
+        "workspace": {
+            "id": workspace_id(window.workspace),
+            "name": workspace_name(window.workspace),
+        },
+        "title": title,
+        "class": class,
+        "initialClass": class,
+        "initialTitle": title,
+        "pid": node["pid"],
+        "pinned": node["sticky"].as_bool().unwrap_or(false),
+        "xwayland": node["shell"].as_str() == Some("xwayland"),
+        "monitor": window.monitor,
+        "floating": node["type"].as_str() == Some("floating_con"),
+        "at": [rect["x"], rect["y"]],
+        "size": [rect["width"], rect["height"]],
+        // Sway has no maximized state, only fullscreen.
+        "fullscreen": if node["fullscreen_mode"].as_i64().unwrap_or(0) == 0 { 0 } else { 2 },
+        "focusHistoryID": focus_history_id,
+        "urgent": node["urgent"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Answers `clients`.
+fn clients(tree: &Value) -> Vec<Value> {
+    let order = focus_order(tree);
+    windows(tree)
+        .iter()
+        .map(|window| {
+            let id = window.node["id"].as_i64();
+            let history = order.iter().position(|&f| Some(f) == id);
+            client(window, history.map_or(-1, |index| index as i32))
+        })
+        .collect()
+}
+
+/// Answers `activewindow`: the focused window, or `{}` like Hyprland if none is.
+fn active_window(tree: &Value) -> Value {
+    let order = focus_order(tree);
+    windows(tree)
+        .iter()
+        .find(|window| window.node["focused"].as_bool() == Some(true))
+        .map(|window| {
+            let id = window.node["id"].as_i64();
+            let history = order.iter().position(|&f| Some(f) == id);
+            client(window, history.map_or(0, |index| index as i32))
+        })
+        .unwrap_or_else(|| json!({}))
+}
+
+/// Answers `activeworkspace` from Sway's workspace list.
+fn active_workspace(workspaces: &Value) -> Value {
+    workspaces
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|w| w["focused"].as_bool() == Some(true))
+        .map(|w| {
+            json!({
+                "id": workspace_id(w),
+                "name": workspace_name(w),
+                "monitor": w["output"],
+            })
+        })
+        .unwrap_or_else(|| json!({}))
+}
+
+/// Answers `workspaces`, the scratchpad included as the special workspace.
+fn workspaces(tree: &Value) -> Vec<Value> {
+    let windows = windows(tree);
+    tree_workspaces(tree)
+        .map(|workspace| {
+            let id = workspace_id(workspace);
+            let output = workspace["output"].as_str();
+            let monitor = outputs(tree).position(|o| o["name"].as_str() == output);
+            json!({
+                "id": id,
+                "name": workspace_name(workspace),
+                "windows": windows.iter().filter(|w| workspace_id(w.workspace) == id).count(),
+                "monitorID": monitor.filter(|_| id != SCRATCHPAD_ID),
+            })
+        })
+        .collect()
+}
+
+/// Answers `monitors` from Sway's output list, numbered in tree order.
+fn monitors(tree: &Value, outputs_reply: &Value) -> Vec<Value> {
+    let active = outputs_reply
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|o| o["active"].as_bool().unwrap_or(true));
+    active
+        .enumerate()
+        .map(|(index, output)| {
+            let name = output["name"].as_str();
+            let id = outputs(tree)
+                .position(|o| o["name"].as_str() == name)
+                .unwrap_or(index);
+            let current = output["current_workspace"].as_str();
+            let workspace = tree_workspaces(tree).find(|w| w["name"].as_str() == current);
+            // Hyprland reports the size in physical pixels; Sway's rect is logical.
+            let scale = output["scale"].as_f64().unwrap_or(1.0);
+            let rect = &output["rect"];
+            let physical = |key: &str| (rect[key].as_f64().unwrap_or(0.0) * scale).round() as i64;
+            json!({
+                "id": id,
+                "name": name,
+                "x": rect["x"],
+                "y": rect["y"],
+                "width": physical("width"),
+                "height": physical("height"),
+                "scale": scale,
+                // The rect is in the output's rotated orientation already.
+                "transform": 0,
+                "activeWorkspace": {
+                    "id": workspace.map_or(0, workspace_id),
+                    "name": current.unwrap_or_default(),
+                },
+                "specialWorkspace": { "id": 0, "name": "" },
+                "focused": output["focused"].as_bool().unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+// --- Dispatches ---
+
+/// Quotes a workspace or output name for a Sway command.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the container id of the window a dispatch gives as `address:0x...`.
+fn window_id(window: &str) -> Result<i64, String> {
+    window
+        .strip_prefix("address:")
+        .and_then(container_id)
+        .ok_or_else(|| format!("invalid window '{}'", window))
+}
+
+/// Returns the Sway criteria selecting the window a dispatch gives as `address:0x...`.
+fn criteria(window: &str) -> Result<String, String> {
+    Ok(format!("[con_id={}]", window_id(window)?))
+}
+
+/// Returns how a Sway command names the workspace a dispatch gives by id or as `name:NAME`.
+fn workspace_target(workspace: &str, tree: &Value) -> String {
+    if let Some(name) = workspace.strip_prefix("name:") {
+        return quote(name);
+    }
+    let Ok(id) = workspace.parse::<i32>() else {
+        return quote(workspace);
+    };
+    match tree_workspaces(tree).find(|w| workspace_id(w) == id) {
+        Some(w) if w["num"].as_i64() == Some(id as i64) => format!("number {}", id),
+        Some(w) => quote(w["name"].as_str().unwrap_or_default()),
+        // Sway creates numbered workspaces on demand, like Hyprland.
+        None => format!("number {}", id),
+    }
+}
+
+/// Returns true if the window with the given container id is in the scratchpad.
+fn in_scratchpad(tree: &Value, id: i64) -> bool {
+    windows(tree)
+        .iter()
+        .any(|w| w.node["id"].as_i64() == Some(id) && workspace_id(w.workspace) == SCRATCHPAD_ID)
+}
+
+/// Translates a Hyprland dispatch into a Sway command. Dispatches Sway has no
+/// counterpart of give the reply to report instead.
+fn translate(dispatch: &str, tree: &Value) -> Result<String, String> {
+    let (dispatcher, arg) = dispatch.split_once(' ').unwrap_or((dispatch, ""));
+    let unsupported = || Err(format!("'{}' is not supported on Sway", dispatcher));
+    match dispatcher {
+        "movetoworkspace" | "movetoworkspacesilent" => {
+            let (workspace, window) = arg
+                .rsplit_once(',')
+                .ok_or_else(|| format!("invalid arguments '{}'", arg))?;
+            let id = window_id(window)?;
+            let window = criteria(window)?;
+            if workspace == crate::special_workspace() {
+                return Ok(format!("{} move scratchpad", window));
+            }
+            let mut commands = Vec::new();
+            // Hidden scratchpad windows have to be shown before they can be moved,
+            // and only stop being scratchpad windows once they are tiled.
+            if in_scratchpad(tree, id) {
+                commands.push(format!("{} scratchpad show", window));
+                commands.push(format!("{} floating disable", window));
+            }
+            commands.push(format!(
+                "{} move container to workspace {}",
+                window,
+                workspace_target(workspace, tree)
+            ));
+            if dispatcher == "movetoworkspace" {
+                commands.push(format!("{} focus", window));
+            }
+            Ok(commands.join("; "))
+        }
+        "focuswindow" => Ok(format!("{} focus", criteria(arg)?)),
+        "closewindow" => Ok(format!("{} kill", criteria(arg)?)),
+        "pin" => Ok(format!("{} sticky toggle", criteria(arg)?)),
+        "setfloating" => Ok(format!("{} floating enable", criteria(arg)?)),
+        "resizewindowpixel" | "movewindowpixel" => {
+            let (geometry, window) = arg
+                .strip_prefix("exact ")
+                .and_then(|rest| rest.rsplit_once(','))
+                .ok_or_else(|| format!("invalid arguments '{}'", arg))?;
+            let (x, y) = geometry
+                .split_once(' ')
+                .ok_or_else(|| format!("invalid arguments '{}'", arg))?;
+            let window = criteria(window)?;
+            Ok(match dispatcher {
+                "resizewindowpixel" => {
+                    format!("{} resize set width {} px height {} px", window, x, y)
+                }
+                _ => format!("{} move absolute position {} {}", window, x, y),
+            })
+        }
+        // Both of Hyprland's modes end up as Sway's only one.
+        "fullscreen" => Ok("fullscreen enable".to_string()),
+        "workspace" => Ok(format!("workspace {}", workspace_target(arg, tree))),
+        "togglespecialworkspace" => Ok("scratchpad show".to_string()),
+        "movewindow" => match arg.strip_prefix("mon:") {
+            Some(output) => Ok(format!("move container to output {}", quote(output))),
+            None => unsupported(),
+        },
+        _ => unsupported(),
+    }
+}
+
+// --- Events ---
+
+/// Turns a Sway window event into the Hyprland event lines it stands for.
+/// `workspace` is where the window is now, for `move` events.
+fn event_lines(event: &Value, workspace: Option<&Value>) -> Vec<String> {
+    let container = &event["container"];
+    let Some(id) = container["id"].as_i64() else {
+        return Vec::new();
+    };
+    let raw = format!("{:x}", id);
+    let title = container["name"].as_str().unwrap_or_default();
+    let class = container["app_id"]
+        .as_str()
+        .or_else(|| container["window_properties"]["class"].as_str())
+        .unwrap_or_default();
+    match event["change"].as_str().unwrap_or_default() {
+        "new" => vec![format!("openwindow>>{},,{},{}", raw, class, title)],
+        "close" => vec![format!("closewindow>>{}", raw)],
+        "title" => vec![
+            format!("windowtitle>>{}", raw),
+            format!("windowtitlev2>>{},{}", raw, title),
+        ],
+        "focus" => vec![
+            format!("activewindow>>{},{}", class, title),
+            format!("activewindowv2>>{}", raw),
+        ],
+        "urgent" if container["urgent"].as_bool() == Some(true) => {
+            vec![format!("urgent>>{}", raw)]
+        }
+        "move" => {
+            let (id, name) =
+                workspace.map_or((0, String::new()), |w| (workspace_id(w), workspace_name(w)));
+            vec![
+                format!("movewindow>>{},{}", raw, name),
+                format!("movewindowv2>>{},{},{}", raw, id, name),
+            ]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Sway's window events, as Hyprland event lines.
+pub struct Events {
+    stream: tokio::net::UnixStream,
+    timeout: Duration,
+}
+
+impl Events {
+    /// Connects to Sway and subscribes to window events.
+    pub async fn subscribe(timeout: Duration) -> Result<Events> {
+        let path = socket_path()?;
+        let stream = tokio::net::UnixStream::connect(&path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", path.display()))?;
+        let mut events = Events { stream, timeout };
+        events
+            .stream
+            .write_all(&encode(SUBSCRIBE, r#"["window"]"#))
+            .await?;
+        let (_, reply) = events.read().await?;
+        if reply["success"].as_bool() != Some(true) {
+            bail!("Sway refused the subscription to window events");
+        }
+        Ok(events)
+    }
+
+    /// Reads the next message, returning its type and payload.
+    async fn read(&mut self) -> Result<(u32, Value)> {
+        let mut header = [0u8; 14];
+        self.stream.read_exact(&mut header).await?;
+        let (length, kind) = decode_header(&header)?;
+        let mut payload = vec![0u8; length];
+        self.stream.read_exact(&mut payload).await?;
+        Ok((kind, serde_json::from_slice(&payload)?))
+    }
+
+    /// Waits for the next window event and returns its lines. Returns `None`
+    /// when Sway closed the connection.
+    pub async fn next(&mut self) -> Result<Option<Vec<String>>> {
+        loop {
+            let (kind, event) = match self.read().await {
+                Ok(message) => message,
+                Err(e) if is_closed(&e) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if kind != WINDOW_EVENT {
+                continue;
+            }
+            let workspace_tree = if event["change"].as_str() == Some("move") {
+                let backend = SwayBackend::from_env(self.timeout);
+                tokio::task::spawn_blocking(move || backend.tree())
+                    .await?
+                    .ok()
+            } else {
+                None
+            };
+            let id = event["container"]["id"].as_i64();
+            let workspace = workspace_tree.as_ref().and_then(|tree| {
+                windows(tree)
+                    .into_iter()
+                    .find(|w| w.node["id"].as_i64() == id)
+                    .map(|w| w.workspace)
+            });
+            let lines = event_lines(&event, workspace);
+            if !lines.is_empty() {
+                return Ok(Some(lines));
+            }
+        }
+    }
+}
+
+/// Returns true if the error is Sway closing the connection.
+fn is_closed(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tree with a tiled and a floating XWayland window on workspace 1, the
+    /// tiled one focused, an empty named workspace, and a window in the scratchpad.
+    fn tree() -> Value {
+        json!({
+            "id": 1, "type": "root", "focus": [3, 2],
+            "nodes": [
+                {"id": 2, "type": "output", "name": "__i3", "focus": [], "nodes": [
+                    {"id": 5, "type": "workspace", "name": "__i3_scratch", "num": -1,
+                     "output": "__i3", "nodes": [], "floating_nodes": [
+                        {"id": 12, "type": "floating_con", "name": "Mail", "app_id": "thunderbird",
+                         "pid": 300, "nodes": [], "floating_nodes": [],
+                         "rect": {"x": 0, "y": 0, "width": 800, "height": 600}}
+                     ]}
+                ]},
+                {"id": 3, "type": "output", "name": "eDP-1", "focus": [4, 6], "nodes": [
+                    {"id": 4, "type": "workspace", "name": "1", "num": 1, "output": "eDP-1",
+                     "focus": [11, 10],
+                     "nodes": [
+                        {"id": 10, "type": "con", "name": "Terminal", "app_id": "foot",
+                         "pid": 100, "focused": true, "nodes": [], "floating_nodes": [],
+                         "fullscreen_mode": 1,
+                         "rect": {"x": 0, "y": 0, "width": 960, "height": 1080}}
+                     ],
+                     "floating_nodes": [
+                        {"id": 11, "type": "floating_con", "name": "Old App", "app_id": null,
+                         "window_properties": {"class": "OldApp"}, "shell": "xwayland",
+                         "pid": 200, "sticky": true, "nodes": [], "floating_nodes": [],
+                         "rect": {"x": 100, "y": 50, "width": 400, "height": 300}}
+                     ]},
+                    {"id": 6, "type": "workspace", "name": "mail", "num": -1, "output": "eDP-1",
+                     "nodes": [], "floating_nodes": []}
+                ]}
+            ]
+        })
+    }
+
+    fn client_by_address<'a>(clients: &'a [Value], address: &str) -> &'a Value {
+        clients.iter().find(|c| c["address"] == address).unwrap()
+    }
+
+    #[test]
+    fn describes_windows_like_hyprland_clients() {
+        let clients = clients(&tree());
+        assert_eq!(clients.len(), 3);
+
+        let terminal = client_by_address(&clients, "0xa");
+        assert_eq!(terminal["workspace"], json!({"id": 1, "name": "1"}));
+        assert_eq!(terminal["class"], "foot");
+        assert_eq!(terminal["monitor"], 0);
+        assert_eq!(terminal["fullscreen"], 2);
+        assert_eq!(terminal["floating"], false);
+
+        let old_app = client_by_address(&clients, "0xb");
+        assert_eq!(old_app["class"], "OldApp");
+        assert_eq!(old_app["xwayland"], true);
+        assert_eq!(old_app["floating"], true);
+        assert_eq!(old_app["pinned"], true);
+        assert_eq!(old_app["at"], json!([100, 50]));
+
+        let mail = client_by_address(&clients, "0xc");
+        assert_eq!(
+            mail["workspace"],
+            json!({"id": SCRATCHPAD_ID, "name": "special:minimized"})
+        );
+    }
+
+    #[test]
+    fn focus_history_follows_the_focus_stacks() {
+        let clients = clients(&tree());
+        let history = |address| client_by_address(&clients, address)["focusHistoryID"].clone();
+        assert_eq!(history("0xb"), 0);
+        assert_eq!(history("0xa"), 1);
+        assert_eq!(history("0xc"), 2);
+        assert_eq!(active_window(&tree())["address"], "0xa");
+    }
+
+    #[test]
+    fn lists_workspaces_with_the_scratchpad_as_the_special_one() {
+        let workspaces = workspaces(&tree());
+        let scratchpad = workspaces
+            .iter()
+            .find(|w| w["id"] == SCRATCHPAD_ID)
+            .unwrap();
+        assert_eq!(scratchpad["name"], "special:minimized");
+        assert_eq!(scratchpad["windows"], 1);
+        let first = workspaces.iter().find(|w| w["id"] == 1).unwrap();
+        assert_eq!(first["windows"], 2);
+        assert_eq!(first["monitorID"], 0);
+    }
+
+    #[test]
+    fn named_workspaces_get_a_stable_id_that_leads_back_to_them() {
+        let mail = workspace_id(&json!({"name": "mail", "num": -1}));
+        assert!(mail >= NAMED_WORKSPACE_BASE);
+        assert_eq!(mail, workspace_id(&json!({"name": "mail", "num": -1})));
+        assert_eq!(workspace_target(&mail.to_string(), &tree()), "\"mail\"");
+        assert_eq!(workspace_target("1", &tree()), "number 1");
+        assert_eq!(workspace_target("name:3: web", &tree()), "\"3: web\"");
+    }
+
+    #[test]
+    fn minimizing_moves_to_the_scratchpad() {
+        assert_eq!(
+            translate(
+                "movetoworkspacesilent special:minimized,address:0xa",
+                &tree()
+            ),
+            Ok("[con_id=10] move scratchpad".to_string())
+        );
+    }
+
+    #[test]
+    fn restoring_takes_the_window_out_of_the_scratchpad() {
+        assert_eq!(
+            translate("movetoworkspace 1,address:0xc", &tree()),
+            Ok(
+                "[con_id=12] scratchpad show; [con_id=12] floating disable; \
+                [con_id=12] move container to workspace number 1; [con_id=12] focus"
+                    .to_string()
+            )
+        );
+        // Windows outside the scratchpad just move.
+        assert_eq!(
+            translate("movetoworkspacesilent name:mail,address:0xa", &tree()),
+            Ok("[con_id=10] move container to workspace \"mail\"".to_string())
+        );
+    }
+
+    #[test]
+    fn translates_window_dispatches_to_criteria_commands() {
+        let tree = tree();
+        let cases = [
+            ("focuswindow address:0xa", "[con_id=10] focus"),
+            ("closewindow address:0xa", "[con_id=10] kill"),
+            ("pin address:0xb", "[con_id=11] sticky toggle"),
+            ("setfloating address:0xb", "[con_id=11] floating enable"),
+            (
+                "resizewindowpixel exact 400 300,address:0xb",
+                "[con_id=11] resize set width 400 px height 300 px",
+            ),
+            (
+                "movewindowpixel exact 100 50,address:0xb",
+                "[con_id=11] move absolute position 100 50",
+            ),
+            (
+                "movewindow mon:HDMI-A-1",
+                "move container to output \"HDMI-A-1\"",
+            ),
+            ("togglespecialworkspace minimized", "scratchpad show"),
+        ];
+        for (dispatch, command) in cases {
+            assert_eq!(translate(dispatch, &tree), Ok(command.to_string()));
+        }
+    }
+
+    #[test]
+    fn dispatches_without_a_sway_counterpart_are_rejected() {
+        let reply = translate("renameworkspace -99 Minimized (2)", &tree()).unwrap_err();
+        assert_eq!(reply, "'renameworkspace' is not supported on Sway");
+        assert!(translate("focuswindow pid:100", &tree()).is_err());
+    }
+
+    #[test]
+    fn window_events_become_hyprland_event_lines() {
+        let event = |change: &str| json!({"change": change, "container": {"id": 10, "name": "vim", "app_id": "foot", "urgent": true}});
+        assert_eq!(event_lines(&event("close"), None), ["closewindow>>a"]);
+        assert_eq!(event_lines(&event("urgent"), None), ["urgent>>a"]);
+        assert_eq!(
+            event_lines(&event("title"), None),
+            ["windowtitle>>a", "windowtitlev2>>a,vim"]
+        );
+        let scratchpad = json!({"name": "__i3_scratch", "num": -1});
+        assert_eq!(
+            event_lines(&event("move"), Some(&scratchpad)),
+            [
+                "movewindow>>a,special:minimized",
+                "movewindowv2>>a,-99,special:minimized"
+            ]
+        );
+        assert!(event_lines(&event("mark"), None).is_empty());
+    }
+}
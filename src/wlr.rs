@@ -0,0 +1,998 @@
+//! The wlroots backend of `--backend wlr`, for compositors implementing the
+//! wlr-foreign-toplevel-management protocol, e.g. river, labwc or Wayfire.
+//! This module speaks the Wayland wire protocol on `$WAYLAND_DISPLAY` itself
+//! and answers the minimizer's Hyprland queries and dispatches from the
+//! toplevels the compositor announces, so everything above [`Compositor`]
+//! stays the same:
+//!
+//! - windows are addressed by the id of their toplevel handle, which only
+//!   holds on this process's connection;
+//! - minimized windows are on the special workspace, all others on workspace 1;
+//! - minimizing and restoring use the protocol's minimize requests, focusing
+//!   its activate request;
+//! - changes to the toplevels are turned into the event lines Hyprland would send.
+//!
+//! The protocol knows no monitors, so `monitors` is answered with none.
+//! Everything else, e.g. other workspaces and geometry, stays Hyprland-only:
+//! such queries fail and such dispatches are answered with an error.
+
+use crate::ipc::Compositor;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// --- Wayland wire protocol ---
+
+/// The id of `wl_display`, the one object every connection starts with.
+const DISPLAY: u32 = 1;
+/// Where the ids of objects the compositor creates start.
+const SERVER_ID_BASE: u32 = 0xff00_0000;
+
+// Requests.
+const DISPLAY_SYNC: u16 = 0;
+const DISPLAY_GET_REGISTRY: u16 = 1;
+const REGISTRY_BIND: u16 = 0;
+const HANDLE_SET_MINIMIZED: u16 = 2;
+const HANDLE_UNSET_MINIMIZED: u16 = 3;
+const HANDLE_ACTIVATE: u16 = 4;
+const HANDLE_CLOSE: u16 = 5;
+const HANDLE_DESTROY: u16 = 7;
+
+// Events.
+const DISPLAY_ERROR: u16 = 0;
+const DISPLAY_DELETE_ID: u16 = 1;
+const REGISTRY_GLOBAL: u16 = 0;
+const CALLBACK_DONE: u16 = 0;
+const MANAGER_TOPLEVEL: u16 = 0;
+const MANAGER_FINISHED: u16 = 1;
+const HANDLE_TITLE: u16 = 0;
+const HANDLE_APP_ID: u16 = 1;
+const HANDLE_STATE: u16 = 4;
+const HANDLE_DONE: u16 = 5;
+const HANDLE_CLOSED: u16 = 6;
+
+// Entries of a handle's `state` array.
+const STATE_MAXIMIZED: u32 = 0;
+const STATE_MINIMIZED: u32 = 1;
+const STATE_ACTIVATED: u32 = 2;
+const STATE_FULLSCREEN: u32 = 3;
+
+const MANAGER_INTERFACE: &str = "zwlr_foreign_toplevel_manager_v1";
+/// The newest manager version known here; 3 added `parent`, which is ignored.
+const MANAGER_VERSION: u32 = 3;
+const SEAT_INTERFACE: &str = "wl_seat";
+
+/// Returns the path of the compositor's Wayland socket.
+fn socket_path() -> Result<PathBuf> {
+    let display = PathBuf::from(std::env::var_os("WAYLAND_DISPLAY").unwrap_or("wayland-0".into()));
+    if display.is_absolute() {
+        return Ok(display);
+    }
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").ok_or_else(|| {
+        anyhow!("XDG_RUNTIME_DIR is not set — are you running inside a Wayland session?")
+    })?;
+    Ok(PathBuf::from(runtime_dir).join(display))
+}
+
+/// An argument of a request.
+enum Arg<'a> {
+    Uint(u32),
+    Str(&'a str),
+}
+
+/// Frames a request: the object id, then the message size and the opcode in
+/// one word, both in native byte order, then the arguments padded to 4 bytes.
+fn encode(object: u32, opcode: u16, args: &[Arg]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Uint(value) => body.extend_from_slice(&value.to_ne_bytes()),
+            Arg::Str(s) => {
+                // The length counts the terminating NUL.
+                body.extend_from_slice(&(s.len() as u32 + 1).to_ne_bytes());
+                body.extend_from_slice(s.as_bytes());
+                body.push(0);
+                body.resize(body.len().next_multiple_of(4), 0);
+            }
+        }
+    }
+    let size = body.len() as u32 + 8;
+    let mut message = object.to_ne_bytes().to_vec();
+    message.extend_from_slice(&((size << 16) | opcode as u32).to_ne_bytes());
+    message.extend_from_slice(&body);
+    message
+}
+
+/// A message from the compositor.
+struct Message {
+    object: u32,
+    opcode: u16,
+    args: Vec<u8>,
+}
+
+/// Takes the first message off `buffer`, if it holds all of it.
+fn decode(buffer: &mut Vec<u8>) -> Result<Option<Message>> {
+    if buffer.len() < 8 {
+        return Ok(None);
+    }
+    let object = u32::from_ne_bytes(buffer[..4].try_into().unwrap());
+    let word = u32::from_ne_bytes(buffer[4..8].try_into().unwrap());
+    let size = (word >> 16) as usize;
+    if size < 8 {
+        bail!("The compositor sent a message of {} bytes", size);
+    }
+    if buffer.len() < size {
+        return Ok(None);
+    }
+    let args = buffer[8..size].to_vec();
+    buffer.drain(..size);
+    Ok(Some(Message {
+        object,
+        opcode: (word & 0xffff) as u16,
+        args,
+    }))
+}
+
+/// Reads the arguments of a message in order.
+struct Args<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Args<'a> {
+    fn uint(&mut self) -> Result<u32> {
+        let (word, rest) = self
+            .data
+            .split_first_chunk::<4>()
+            .ok_or_else(|| anyhow!("The compositor sent a truncated message"))?;
+        self.data = rest;
+        Ok(u32::from_ne_bytes(*word))
+    }
+
+    /// Reads an array without its padding.
+    fn array(&mut self) -> Result<&'a [u8]> {
+        let length = self.uint()? as usize;
+        let padded = length.next_multiple_of(4);
+        if self.data.len() < padded {
+            bail!("The compositor sent a truncated message");
+        }
+        let (array, rest) = self.data.split_at(padded);
+        self.data = rest;
+        Ok(&array[..length])
+    }
+
+    /// Reads a string, which is sent as an array with a terminating NUL.
+    fn string(&mut self) -> Result<String> {
+        let bytes = self.array()?;
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+// --- Toplevels ---
+
+/// What the compositor reported about a toplevel.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Toplevel {
+    title: String,
+    app_id: String,
+    minimized: bool,
+    activated: bool,
+    maximized: bool,
+    fullscreen: bool,
+}
+
+/// A toplevel handle. Its events only take effect with the `done` that follows them.
+#[derive(Default)]
+struct Handle {
+    /// The state as of the last `done`; none until the first one.
+    current: Option<Toplevel>,
+    pending: Toplevel,
+    initial_title: String,
+    initial_app_id: String,
+    /// When the toplevel was last activated, counting activations; 0 if never.
+    activated_at: u64,
+}
+
+/// The connection's part of the process: the objects it bound, the toplevels
+/// and who to tell about changes to them.
+struct State {
+    stream: UnixStream,
+    next_id: u32,
+    /// Ids the compositor is done with, to be used again.
+    free_ids: Vec<u32>,
+    registry: u32,
+    /// The globals the registry announced: name, interface and version.
+    globals: Vec<(u32, String, u32)>,
+    manager: Option<u32>,
+    seat: Option<u32>,
+    handles: BTreeMap<u32, Handle>,
+    /// Outstanding `wl_display.sync` callbacks and whether they are done.
+    callbacks: HashMap<u32, bool>,
+    activations: u64,
+    subscribers: Vec<mpsc::UnboundedSender<String>>,
+    /// Why the connection stopped working, once it did.
+    lost: Option<String>,
+}
+
+impl State {
+    fn new(stream: UnixStream) -> State {
+        State {
+            stream,
+            next_id: DISPLAY + 1,
+            free_ids: Vec::new(),
+            registry: 0,
+            globals: Vec::new(),
+            manager: None,
+            seat: None,
+            handles: BTreeMap::new(),
+            callbacks: HashMap::new(),
+            activations: 0,
+            subscribers: Vec::new(),
+            lost: None,
+        }
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.free_ids.pop().unwrap_or_else(|| {
+            self.next_id += 1;
+            self.next_id - 1
+        })
+    }
+
+    fn send(&mut self, object: u32, opcode: u16, args: &[Arg]) -> Result<()> {
+        self.stream
+            .write_all(&encode(object, opcode, args))
+            .context("Failed to send a request to the compositor")
+    }
+
+    /// Fails once the connection stopped working.
+    fn check(&self) -> Result<()> {
+        match &self.lost {
+            Some(reason) => bail!("The Wayland connection was lost: {}", reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Binds the toplevel manager and the first seat from the registry's globals.
+    fn bind(&mut self) -> Result<()> {
+        let find = |interface: &str| {
+            self.globals
+                .iter()
+                .find(|(_, i, _)| i == interface)
+                .map(|(name, _, version)| (*name, *version))
+        };
+        let (manager, seat) = (find(MANAGER_INTERFACE), find(SEAT_INTERFACE));
+        let Some((name, version)) = manager else {
+            bail!(
+                "The compositor does not support wlr-foreign-toplevel-management ({})",
+                MANAGER_INTERFACE
+            );
+        };
+        let id = self.new_id();
+        self.manager = Some(id);
+        let version = version.min(MANAGER_VERSION);
+        let args = [
+            Arg::Uint(name),
+            Arg::Str(MANAGER_INTERFACE),
+            Arg::Uint(version),
+            Arg::Uint(id),
+        ];
+        self.send(self.registry, REGISTRY_BIND, &args)?;
+        // Without a seat, windows can still be minimized, just not focused.
+        if let Some((name, _)) = seat {
+            let id = self.new_id();
+            self.seat = Some(id);
+            let args = [
+                Arg::Uint(name),
+                Arg::Str(SEAT_INTERFACE),
+                Arg::Uint(1),
+                Arg::Uint(id),
+            ];
+            self.send(self.registry, REGISTRY_BIND, &args)?;
+        }
+        Ok(())
+    }
+
+    /// Handles an event. Errors are fatal for the connection.
+    fn handle(&mut self, message: Message) -> Result<()> {
+        let mut args = Args {
+            data: &message.args,
+        };
+        match (message.object, message.opcode) {
+            (DISPLAY, DISPLAY_ERROR) => {
+                let object = args.uint()?;
+                let code = args.uint()?;
+                let text = args.string()?;
+                bail!(
+                    "The compositor reported error {} on object {}: {}",
+                    code,
+                    object,
+                    text
+                );
+            }
+            (DISPLAY, DISPLAY_DELETE_ID) => {
+                let id = args.uint()?;
+                if id < SERVER_ID_BASE {
+                    self.free_ids.push(id);
+                }
+            }
+            (object, REGISTRY_GLOBAL) if object == self.registry => {
+                let name = args.uint()?;
+                let interface = args.string()?;
+                let version = args.uint()?;
+                self.globals.push((name, interface, version));
+            }
+            (object, MANAGER_TOPLEVEL) if Some(object) == self.manager => {
+                self.handles.insert(args.uint()?, Handle::default());
+            }
+            (object, MANAGER_FINISHED) if Some(object) == self.manager => {
+                bail!("The compositor stopped announcing toplevels");
+            }
+            (object, opcode) if self.handles.contains_key(&object) => {
+                self.handle_toplevel(object, opcode, args)?;
+            }
+            (object, CALLBACK_DONE) if self.callbacks.contains_key(&object) => {
+                self.callbacks.insert(object, true);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_toplevel(&mut self, id: u32, opcode: u16, mut args: Args) -> Result<()> {
+        let handle = self.handles.get_mut(&id).unwrap();
+        match opcode {
+            HANDLE_TITLE => handle.pending.title = args.string()?,
+            HANDLE_APP_ID => handle.pending.app_id = args.string()?,
+            HANDLE_STATE => {
+                let states: Vec<u32> = args
+                    .array()?
+                    .chunks_exact(4)
+                    .map(|entry| u32::from_ne_bytes(entry.try_into().unwrap()))
+                    .collect();
+                let pending = &mut handle.pending;
+                pending.maximized = states.contains(&STATE_MAXIMIZED);
+                pending.minimized = states.contains(&STATE_MINIMIZED);
+                pending.activated = states.contains(&STATE_ACTIVATED);
+                pending.fullscreen = states.contains(&STATE_FULLSCREEN);
+            }
+            HANDLE_DONE => {
+                let lines = event_lines(id, handle.current.as_ref(), &handle.pending);
+                if handle.current.is_none() {
+                    handle.initial_title = handle.pending.title.clone();
+                    handle.initial_app_id = handle.pending.app_id.clone();
+                }
+                if handle.pending.activated && !handle.current.as_ref().is_some_and(|c| c.activated)
+                {
+                    self.activations += 1;
+                    handle.activated_at = self.activations;
+                }
+                handle.current = Some(handle.pending.clone());
+                self.publish(lines);
+            }
+            HANDLE_CLOSED => {
+                if let Some(Handle {
+                    current: Some(_), ..
+                }) = self.handles.remove(&id)
+                {
+                    self.publish(vec![format!("closewindow>>{:x}", id)]);
+                }
+                self.send(id, HANDLE_DESTROY, &[])?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Hands event lines to the event streams, dropping the ones that went away.
+    fn publish(&mut self, lines: Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+        self.subscribers.retain(|subscriber| {
+            lines
+                .iter()
+                .all(|line| subscriber.send(line.clone()).is_ok())
+        });
+    }
+}
+
+/// The process's connection to the compositor. A thread reads its events, so
+/// the toplevels stay current and event streams get told about changes.
+struct Connection {
+    state: Mutex<State>,
+    /// Notified whenever the reader thread handled events.
+    changed: Condvar,
+}
+
+/// The connection, once made. It lives as long as the process, as handle ids,
+/// and with them window addresses, only hold on the connection they came from.
+static CONNECTION: Mutex<Option<&'static Connection>> = Mutex::new(None);
+
+/// Returns the process's connection, connecting on first use.
+fn connection(timeout: Duration) -> Result<&'static Connection> {
+    let mut connection = CONNECTION.lock().unwrap();
+    if let Some(connection) = *connection {
+        return Ok(connection);
+    }
+    let path = socket_path()?;
+    let stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to {}", path.display()))?;
+    // A hung compositor would otherwise block the caller forever.
+    stream.set_write_timeout(Some(timeout))?;
+    let reader = stream.try_clone()?;
+    let new: &'static Connection = Box::leak(Box::new(Connection {
+        state: Mutex::new(State::new(stream)),
+        changed: Condvar::new(),
+    }));
+    std::thread::Builder::new()
+        .name("wayland".to_string())
+        .spawn(move || new.read_events(reader))
+        .context("Failed to start the Wayland event thread")?;
+    if let Err(e) = new.set_up(timeout) {
+        // Ends the reader thread.
+        let _ = new.state.lock().unwrap().stream.shutdown(Shutdown::Both);
+        return Err(e);
+    }
+    *connection = Some(new);
+    Ok(new)
+}
+
+impl Connection {
+    /// Fetches the globals, binds the manager and waits for the toplevels it announces.
+    fn set_up(&self, timeout: Duration) -> Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            let registry = state.new_id();
+            state.registry = registry;
+            state.send(DISPLAY, DISPLAY_GET_REGISTRY, &[Arg::Uint(registry)])?;
+        }
+        self.roundtrip(timeout)?.bind()?;
+        self.roundtrip(timeout).map(drop)
+    }
+
+    /// Waits until the compositor handled all requests sent so far and the
+    /// events they caused arrived, and returns the state then.
+    fn roundtrip(&self, timeout: Duration) -> Result<MutexGuard<'_, State>> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let callback = state.new_id();
+        state.callbacks.insert(callback, false);
+        state.send(DISPLAY, DISPLAY_SYNC, &[Arg::Uint(callback)])?;
+        let (mut state, _) = self
+            .changed
+            .wait_timeout_while(state, timeout, |s| {
+                s.lost.is_none() && s.callbacks.get(&callback) == Some(&false)
+            })
+            .unwrap();
+        state.check()?;
+        if state.callbacks.remove(&callback) != Some(true) {
+            bail!(
+                "The compositor did not answer within {}ms",
+                timeout.as_millis()
+            );
+        }
+        Ok(state)
+    }
+
+    /// Reads and handles events until the connection fails.
+    fn read_events(&self, mut stream: UnixStream) {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let lost = 'read: loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => break "the compositor closed the connection".to_string(),
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => break e.to_string(),
+            }
+            let mut state = self.state.lock().unwrap();
+            loop {
+                let handled = match decode(&mut buffer) {
+                    Ok(Some(message)) => state.handle(message),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = handled {
+                    break 'read format!("{:#}", e);
+                }
+            }
+            drop(state);
+            self.changed.notify_all();
+        };
+        log::warn!("[Wayland] Connection lost: {}", lost);
+        let mut state = self.state.lock().unwrap();
+        state.lost.get_or_insert(lost);
+        // Dropping the senders ends the event streams.
+        state.subscribers.clear();
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+// --- Queries ---
+
+/// The id the special workspace goes by, negative like Hyprland's.
+const SPECIAL_ID: i32 = -99;
+/// The workspace all windows that are not minimized are on.
+const WORKSPACE_ID: i32 = 1;
+
+/// Returns the id and name of the workspace a window is on.
+fn workspace(minimized: bool) -> (i32, String) {
+    if minimized {
+        (SPECIAL_ID, crate::special_workspace().to_string())
+    } else {
+        (WORKSPACE_ID, WORKSPACE_ID.to_string())
+    }
+}
+
+/// Returns the window address of a handle id.
+fn address(id: u32) -> String {
+    format!("0x{:x}", id)
+}
+
+/// Returns the handle id of the window a dispatch gives as `address:0x...`.
+fn handle_id(window: &str) -> Result<u32, String> {
+    window
+        .strip_prefix("address:0x")
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| format!("invalid window '{}'", window))
+}
+
+/// Returns the toplevels that are done being announced, with their handle ids.
+fn toplevels(handles: &BTreeMap<u32, Handle>) -> impl Iterator<Item = (u32, &Handle, &Toplevel)> {
+    handles
+        .iter()
+        .filter_map(|(&id, handle)| Some((id, handle, handle.current.as_ref()?)))
+}
+
+/// Returns the handle ids, most recently activated first.
+fn focus_order(handles: &BTreeMap<u32, Handle>) -> Vec<u32> {
+    let mut order: Vec<(u32, u64)> = toplevels(handles)
+        .map(|(id, handle, _)| (id, handle.activated_at))
+        .collect();
+    order.sort_by_key(|&(id, activated_at)| std::cmp::Reverse((activated_at, id)));
+    order.into_iter().map(|(id, _)| id).collect()
+}
+
+/// Describes a toplevel the way `hyprctl -j clients` does. The protocol knows
+/// nothing about pids, monitors or geometry, so those are left empty.
+fn client(id: u32, handle: &Handle, toplevel: &Toplevel, focus_history_id: i32) -> Value {
+    let (workspace_id, workspace_name) = workspace(toplevel.minimized);
+    json!({
+        "address": address(id),
+        "workspace": { "id": workspace_id, "name": workspace_name },
+        "title": toplevel.title,
+        "class": toplevel.app_id,
+        "initialClass": handle.initial_app_id,
+        "initialTitle": handle.initial_title,
+        "pid": -1,
+        "pinned": false,
+        "xwayland": false,
+        "monitor": 0,
+        "floating": false,
+        "at": [0, 0],
+        "size": [0, 0],
+        "fullscreen": toplevel.maximized as i32 | (toplevel.fullscreen as i32) << 1,
+        "focusHistoryID": focus_history_id,
+    })
+}
+
+/// Answers `clients`.
+fn clients(handles: &BTreeMap<u32, Handle>) -> Vec<Value> {
+    let order = focus_order(handles);
+    toplevels(handles)
+        .map(|(id, handle, toplevel)| {
+            let history = order.iter().position(|&f| f == id).unwrap_or_default();
+            client(id, handle, toplevel, history as i32)
+        })
+        .collect()
+}
+
+/// Answers `activewindow`: the activated toplevel, or `{}` like Hyprland if none is.
+fn active_window(handles: &BTreeMap<u32, Handle>) -> Value {
+    let order = focus_order(handles);
+    toplevels(handles)
+        .find(|(_, _, toplevel)| toplevel.activated)
+        .map(|(id, handle, toplevel)| {
+            let history = order.iter().position(|&f| f == id).unwrap_or_default();
+            client(id, handle, toplevel, history as i32)
+        })
+        .unwrap_or_else(|| json!({}))
+}
+
+/// Answers `workspaces`: the one workspace and the special one.
+fn workspaces(handles: &BTreeMap<u32, Handle>) -> Vec<Value> {
+    [false, true]
+        .into_iter()
+        .map(|minimized| {
+            let (id, name) = workspace(minimized);
+            let windows = toplevels(handles)
+                .filter(|(_, _, toplevel)| toplevel.minimized == minimized)
+                .count();
+            json!({
+                "id": id,
+                "name": name,
+                "windows": windows,
+                "monitorID": if minimized { None } else { Some(0) },
+            })
+        })
+        .collect()
+}
+
+// --- Dispatches ---
+
+/// A request on a toplevel handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Request {
+    Minimize,
+    Unminimize,
+    Activate,
+    Close,
+}
+
+impl Request {
+    fn opcode(self) -> u16 {
+        match self {
+            Request::Minimize => HANDLE_SET_MINIMIZED,
+            Request::Unminimize => HANDLE_UNSET_MINIMIZED,
+            Request::Activate => HANDLE_ACTIVATE,
+            Request::Close => HANDLE_CLOSE,
+        }
+    }
+}
+
+/// Translates a Hyprland dispatch into requests on toplevel handles.
+/// Dispatches without a counterpart give the reply to report instead.
+fn translate(dispatch: &str) -> Result<Vec<(u32, Request)>, String> {
+    let (dispatcher, arg) = dispatch.split_once(' ').unwrap_or((dispatch, ""));
+    match dispatcher {
+        "movetoworkspace" | "movetoworkspacesilent" => {
+            let (workspace, window) = arg
+                .rsplit_once(',')
+                .ok_or_else(|| format!("invalid arguments '{}'", arg))?;
+            let id = handle_id(window)?;
+            if workspace == crate::special_workspace() {
+                return Ok(vec![(id, Request::Minimize)]);
+            }
+            // There is only one workspace, so moving anywhere else brings the window back.
+            let mut requests = vec![(id, Request::Unminimize)];
+            if dispatcher == "movetoworkspace" {
+                requests.push((id, Request::Activate));
+            }
+            Ok(requests)
+        }
+        "focuswindow" => Ok(vec![(handle_id(arg)?, Request::Activate)]),
+        "closewindow" => Ok(vec![(handle_id(arg)?, Request::Close)]),
+        _ => Err(format!(
+            "'{}' is not supported with --backend wlr",
+            dispatcher
+        )),
+    }
+}
+
+/// Talks to the compositor over the process's Wayland connection.
+pub struct WlrBackend {
+    timeout: Duration,
+}
+
+impl WlrBackend {
+    /// Returns the backend for the Wayland session in the environment. The
+    /// connection is made by the first request, which also reports its failure.
+    pub fn from_env(timeout: Duration) -> WlrBackend {
+        WlrBackend { timeout }
+    }
+}
+
+impl Compositor for WlrBackend {
+    fn query(&self, command: &str) -> Result<Vec<u8>> {
+        let state = connection(self.timeout)?.roundtrip(self.timeout)?;
+        let reply = match command {
+            "clients" => Value::from(clients(&state.handles)),
+            "activewindow" => active_window(&state.handles),
+            "activeworkspace" => {
+                let (id, name) = workspace(false);
+                json!({ "id": id, "name": name })
+            }
+            "workspaces" => Value::from(workspaces(&state.handles)),
+            "monitors" => json!([]),
+            _ => bail!("'{}' is not available with --backend wlr", command),
+        };
+        Ok(reply.to_string().into_bytes())
+    }
+
+    fn dispatch(&self, command: &str) -> Result<String> {
+        let requests = match translate(command) {
+            Ok(requests) => requests,
+            Err(reply) => return Ok(reply),
+        };
+        let connection = connection(self.timeout)?;
+        {
+            let mut state = connection.state.lock().unwrap();
+            state.check()?;
+            for (id, request) in requests {
+                if state.handles.get(&id).is_none_or(|h| h.current.is_none()) {
+                    return Ok(format!("no window with address {}", address(id)));
+                }
+                if request == Request::Activate {
+                    let Some(seat) = state.seat else {
+                        return Ok("the compositor has no seat to focus windows with".to_string());
+                    };
+                    state.send(id, request.opcode(), &[Arg::Uint(seat)])?;
+                } else {
+                    state.send(id, request.opcode(), &[])?;
+                }
+            }
+        }
+        // Protocol errors arrive once the compositor handled the requests.
+        connection.roundtrip(self.timeout).map(drop)?;
+        Ok("ok".to_string())
+    }
+
+    fn dispatch_batch(&self, commands: &[&str]) -> Result<String> {
+        // Like a Hyprland batch, the replies run together and stop at the first error.
+        let mut replies = String::new();
+        for command in commands {
+            let reply = self.dispatch(command)?;
+            let failed = reply != "ok";
+            replies.push_str(&reply);
+            if failed {
+                break;
+            }
+        }
+        Ok(replies)
+    }
+}
+
+// --- Events ---
+
+/// Returns the Hyprland event lines for a toplevel going from `old` to `new`,
+/// `old` being none for a toplevel that was just announced.
+fn event_lines(id: u32, old: Option<&Toplevel>, new: &Toplevel) -> Vec<String> {
+    let raw = format!("{:x}", id);
+    let (workspace_id, workspace_name) = workspace(new.minimized);
+    let mut lines = Vec::new();
+    match old {
+        None => lines.push(format!(
+            "openwindow>>{},{},{},{}",
+            raw, workspace_name, new.app_id, new.title
+        )),
+        Some(old) => {
+            if new.title != old.title {
+                lines.push(format!("windowtitle>>{}", raw));
+                lines.push(format!("windowtitlev2>>{},{}", raw, new.title));
+            }
+            if new.minimized != old.minimized {
+                lines.push(format!("movewindow>>{},{}", raw, workspace_name));
+                lines.push(format!(
+                    "movewindowv2>>{},{},{}",
+                    raw, workspace_id, workspace_name
+                ));
+            }
+        }
+    }
+    if new.activated && !old.is_some_and(|old| old.activated) {
+        lines.push(format!("activewindow>>{},{}", new.app_id, new.title));
+        lines.push(format!("activewindowv2>>{}", raw));
+    }
+    lines
+}
+
+/// The changes to the toplevels, as Hyprland event lines.
+pub struct Events {
+    lines: mpsc::UnboundedReceiver<String>,
+}
+
+impl Events {
+    /// Starts listening on the process's connection, making it if need be.
+    pub async fn subscribe(timeout: Duration) -> Result<Events> {
+        let connection = tokio::task::spawn_blocking(move || connection(timeout)).await??;
+        let (sender, lines) = mpsc::unbounded_channel();
+        let mut state = connection.state.lock().unwrap();
+        state.check()?;
+        state.subscribers.push(sender);
+        Ok(Events { lines })
+    }
+
+    /// Waits for the next event line. Returns `None` when the connection is lost.
+    pub async fn next(&mut self) -> Option<String> {
+        self.lines.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A terminal that is focused, a browser, and a mail client that is minimized.
+    fn handles() -> BTreeMap<u32, Handle> {
+        let handle = |title: &str, app_id: &str, minimized, activated_at| {
+            let toplevel = Toplevel {
+                title: title.to_string(),
+                app_id: app_id.to_string(),
+                minimized,
+                activated: activated_at == 3,
+                ..Toplevel::default()
+            };
+            Handle {
+                current: Some(toplevel.clone()),
+                pending: toplevel,
+                initial_title: title.to_string(),
+                initial_app_id: app_id.to_string(),
+                activated_at,
+            }
+        };
+        BTreeMap::from([
+            (0xff00_0000, handle("Terminal", "foot", false, 3)),
+            (0xff00_0001, handle("Browser", "firefox", false, 1)),
+            (0xff00_0002, handle("Mail", "thunderbird", true, 2)),
+            // Announced, but not done yet.
+            (0xff00_0003, Handle::default()),
+        ])
+    }
+
+    fn client_by_address<'a>(clients: &'a [Value], address: &str) -> &'a Value {
+        clients.iter().find(|c| c["address"] == address).unwrap()
+    }
+
+    #[test]
+    fn describes_toplevels_like_hyprland_clients() {
+        let clients = clients(&handles());
+        assert_eq!(clients.len(), 3);
+
+        let terminal = client_by_address(&clients, "0xff000000");
+        assert_eq!(terminal["workspace"], json!({"id": 1, "name": "1"}));
+        assert_eq!(terminal["class"], "foot");
+        assert_eq!(terminal["focusHistoryID"], 0);
+
+        let mail = client_by_address(&clients, "0xff000002");
+        assert_eq!(
+            mail["workspace"],
+            json!({"id": SPECIAL_ID, "name": "special:minimized"})
+        );
+        assert_eq!(mail["focusHistoryID"], 1);
+        assert_eq!(
+            client_by_address(&clients, "0xff000001")["focusHistoryID"],
+            2
+        );
+
+        assert_eq!(active_window(&handles())["address"], "0xff000000");
+        let workspaces = workspaces(&handles());
+        assert_eq!(workspaces[0]["windows"], 2);
+        assert_eq!(workspaces[1]["windows"], 1);
+    }
+
+    #[test]
+    fn translates_minimizing_restoring_and_focusing() {
+        assert_eq!(
+            translate("movetoworkspacesilent special:minimized,address:0xff000001"),
+            Ok(vec![(0xff00_0001, Request::Minimize)])
+        );
+        assert_eq!(
+            translate("movetoworkspace 1,address:0xff000001"),
+            Ok(vec![
+                (0xff00_0001, Request::Unminimize),
+                (0xff00_0001, Request::Activate)
+            ])
+        );
+        assert_eq!(
+            translate("movetoworkspacesilent name:1,address:0xff000001"),
+            Ok(vec![(0xff00_0001, Request::Unminimize)])
+        );
+        assert_eq!(
+            translate("focuswindow address:0xff000002"),
+            Ok(vec![(0xff00_0002, Request::Activate)])
+        );
+        assert_eq!(
+            translate("focuswindow pid:100"),
+            Err("invalid window 'pid:100'".to_string())
+        );
+        assert_eq!(
+            translate("pin address:0xff000002"),
+            Err("'pin' is not supported with --backend wlr".to_string())
+        );
+    }
+
+    #[test]
+    fn encodes_requests_with_padded_strings() {
+        let message = encode(
+            2,
+            REGISTRY_BIND,
+            &[Arg::Uint(7), Arg::Str("wl_seat"), Arg::Uint(1)],
+        );
+        // Header, name, the string's length, 8 bytes of string, version.
+        assert_eq!(message.len(), 8 + 4 + 4 + 8 + 4);
+        assert_eq!(&message[..4], &2u32.to_ne_bytes());
+        assert_eq!(
+            &message[4..8],
+            &((28u32 << 16) | REGISTRY_BIND as u32).to_ne_bytes()
+        );
+        assert_eq!(&message[12..16], &8u32.to_ne_bytes());
+        assert_eq!(&message[16..24], b"wl_seat\0");
+    }
+
+    /// Frames an event the way the compositor does, which is the same as a request.
+    fn event(object: u32, opcode: u16, args: &[u8]) -> Vec<u8> {
+        let mut message = object.to_ne_bytes().to_vec();
+        let size = args.len() as u32 + 8;
+        message.extend_from_slice(&((size << 16) | opcode as u32).to_ne_bytes());
+        message.extend_from_slice(args);
+        message
+    }
+
+    fn string_arg(s: &str) -> Vec<u8> {
+        let mut arg = encode(0, 0, &[Arg::Str(s)]);
+        arg.drain(..8);
+        arg
+    }
+
+    #[test]
+    fn toplevel_events_take_effect_on_done_and_become_event_lines() {
+        let (stream, mut compositor) = UnixStream::pair().unwrap();
+        let mut state = State::new(stream);
+        state.manager = Some(3);
+        let (sender, mut lines) = mpsc::unbounded_channel();
+        state.subscribers.push(sender);
+        let id = 0xff00_0000u32;
+        let minimized_and_activated: Vec<u8> =
+            [1u32, 2].iter().flat_map(|s| s.to_ne_bytes()).collect();
+        let mut states = 8u32.to_ne_bytes().to_vec();
+        states.extend_from_slice(&minimized_and_activated);
+
+        let mut buffer = [
+            event(3, MANAGER_TOPLEVEL, &id.to_ne_bytes()),
+            event(id, HANDLE_TITLE, &string_arg("Mail")),
+            event(id, HANDLE_APP_ID, &string_arg("thunderbird")),
+        ]
+        .concat();
+        while let Some(message) = decode(&mut buffer).unwrap() {
+            state.handle(message).unwrap();
+        }
+        assert!(clients(&state.handles).is_empty());
+
+        let mut buffer = event(id, HANDLE_DONE, &[]);
+        buffer.extend_from_slice(&event(id, HANDLE_STATE, &states));
+        buffer.extend_from_slice(&event(id, HANDLE_DONE, &[]));
+        // Half a message stays in the buffer until the rest arrives.
+        buffer.extend_from_slice(&event(id, HANDLE_CLOSED, &[])[..4]);
+        while let Some(message) = decode(&mut buffer).unwrap() {
+            state.handle(message).unwrap();
+        }
+        assert_eq!(buffer.len(), 4);
+        let clients = clients(&state.handles);
+        assert_eq!(clients[0]["workspace"]["id"], SPECIAL_ID);
+        assert_eq!(clients[0]["initialClass"], "thunderbird");
+
+        buffer.clear();
+        buffer.extend_from_slice(&event(id, HANDLE_CLOSED, &[]));
+        state.handle(decode(&mut buffer).unwrap().unwrap()).unwrap();
+        assert!(state.handles.is_empty());
+        // Closing the handle destroys it.
+        let mut sent = [0u8; 8];
+        compositor.read_exact(&mut sent).unwrap();
+        assert_eq!(sent, encode(id, HANDLE_DESTROY, &[])[..]);
+
+        let mut received = Vec::new();
+        while let Ok(line) = lines.try_recv() {
+            received.push(line);
+        }
+        assert_eq!(
+            received,
+            [
+                "openwindow>>ff000000,1,thunderbird,Mail",
+                "movewindow>>ff000000,special:minimized",
+                "movewindowv2>>ff000000,-99,special:minimized",
+                "activewindow>>thunderbird,Mail",
+                "activewindowv2>>ff000000",
+                "closewindow>>ff000000",
+            ]
+        );
+    }
+}